@@ -1,178 +1,3851 @@
-use crate::error;
-use crate::state;
+use crate::clipboard::Clipboard;
+use crate::config::Config;
+use crate::error::{self, AppError};
+use crate::history;
+use crate::input::{self, LineSource, Reader};
+use crate::keymap::KeyMap;
+use crate::render::{Renderer, Terminal};
+use crate::search::{self, Matcher, SearchOptions};
+use crate::state::{self, Anchor, CopyTarget, Placement, Timestamps};
+use crate::terminal::{self, Guard};
+use crate::theme::{self, Theme};
 
-use crossbeam::channel::{select, Receiver};
-use regex::{self, Regex};
-use std::fmt;
+use crossbeam::channel::{self, select, Receiver};
+use flate2::read::MultiGzDecoder;
+use regex::Regex;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::BufRead;
-use std::sync::{Arc, Mutex};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::iter;
+use std::mem;
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use termion::clear;
-use termion::color;
-use termion::event::Key;
+use termion::color::{self, AnsiValue};
+use termion::event::{Event, Key};
 use termion::style;
 use termion::terminal_size;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 pub type Result<T> = std::result::Result<T, error::AppError>;
 
-#[derive(Debug, Copy, Clone)]
-pub enum Mode {
-    Normal,
-    Search,
+// The size of the screen as columns and rows, used when the size of the terminal can't be
+// determined, e.g. when the output isn't a terminal.
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+// A range of bytes of a line, together with the escape sequence that starts its style.
+type Span = (Range<usize>, String);
+
+// Transforms a line that has been read into the line that is added to the buffer.
+type Transform = Box<dyn Fn(&str) -> String>;
+
+// A block of lines that is repeated, and the number of lines it repeats, see `find_blocks()`.
+type Block = (Range<usize>, usize);
+
+// A line that takes up a row: its index, the number of times it's repeated, and whether it
+// matches the query. Separators between groups of lines are repeated 0 times.
+type Row = (usize, usize, bool);
+
+// The largest number of lines of a block that is collapsed when it's repeated.
+const MAX_BLOCK_LINES: usize = 20;
+
+// Lines matching this regex are errors, unless another regex is configured.
+const DEFAULT_ERROR_PATTERN: &str = r"(?i)\b(error|fatal|panic)\b";
+
+// The minimum time between two redraws that are caused by new input.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(20);
+
+// How often the spinner in the footer turns while the input is still being read.
+const SPINNER_INTERVAL: Duration = Duration::from_millis(250);
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+
+// How long highlighting the lines on the screen may take during a redraw, unless another budget
+// is configured.
+const DEFAULT_HIGHLIGHT_BUDGET: Duration = Duration::from_millis(100);
+
+// What the matches in the buffer are found for. As long as it stays the same, they don't have to
+// be found again.
+#[derive(Clone, PartialEq)]
+struct Scan {
+    generation: u64,
+    patterns: Option<Vec<String>>,
+    columns: (Option<usize>, Option<usize>),
+}
+
+// Everything that decides which lines take up a row.
+#[derive(PartialEq)]
+struct Layout {
+    scan: Scan,
+    filter: bool,
+    invert: bool,
+    context: usize,
+    dedup: bool,
+    collapse_blocks: bool,
+    expanded_blocks: HashSet<usize>,
+    exclude: Option<String>,
 }
 
-impl fmt::Display for Mode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+// The lines of the buffer, see `App::lines()`. It derefs to a slice of the lines.
+pub struct Lines<'a>(MutexGuard<'a, VecDeque<String>>);
+
+impl Deref for Lines<'_> {
+    type Target = [String];
+
+    fn deref(&self) -> &[String] {
+        // The buffer has been made contiguous before it was lent, so its first slice has all lines.
+        self.0.as_slices().0
     }
 }
 
 pub struct App<W: io::Write> {
-    raw_buffer: Arc<Mutex<Vec<String>>>,
-    output: W,
+    raw_buffer: Arc<Mutex<VecDeque<String>>>,
+
+    // Applied to every line before it's added to `raw_buffer`, e.g. to redact secrets. The
+    // transformed line is what is shown, saved, and by default searched.
+    line_transform: Option<Transform>,
+
+    // When set, queries are matched against the lines as they were read, before they were
+    // transformed. Those lines are kept in `originals` then, side by side with `raw_buffer`.
+    // The matches can't be highlighted, because they don't line up with the lines that are
+    // shown.
+    search_original: bool,
+    originals: VecDeque<String>,
+
+    // When every line in `raw_buffer` was read. Lines that were read from a file don't have a
+    // time, it wouldn't tell anything about the lines.
+    arrivals: VecDeque<Option<SystemTime>>,
+
+    // When the app started, the elapsed time of lines is counted from here.
+    started: SystemTime,
+
+    // Whether lines are read as they're written, instead of from a file. Only those lines get
+    // timestamps.
+    live: bool,
+
+    // Set while the input is still being read, `spinner` counts the turns of the spinner that
+    // shows it in the footer.
+    streaming: bool,
+    spinner: usize,
+
+    // Where the lines come from. When no input is set, lines are read from STDIN.
+    input: Option<Box<dyn LineSource>>,
+
+    // The maximum number of lines kept in `raw_buffer`. When more lines come in, the oldest
+    // lines are dropped.
+    max_lines: Option<usize>,
+
+    // Of the lines that are already available when the app starts, only this many of the most
+    // recent ones are kept. It's unset once the input has caught up, after that new lines are
+    // only limited by `max_lines`.
+    tail: Option<usize>,
+
+    // When set, nothing is drawn while the input is loading. The screen is first drawn when the
+    // input has been read completely, or when a key is pressed before that.
+    quiet_start: bool,
+
+    // The number of columns between tab stops.
+    tab_width: usize,
+
+    // What is shown in front of the query in search mode, and in front of the command or the
+    // line number in command and goto mode.
+    search_prompt: String,
+    command_prompt: String,
+
+    theme: Theme,
+
+    // The file the search history is kept in. When it isn't set, the history isn't persisted.
+    history_file: Option<PathBuf>,
+
+    // Problems that didn't stop the app, like the history that couldn't be saved. They can't be
+    // shown on the screen once the app has stopped, so it's up to the caller to report them.
+    warnings: Vec<String>,
+
+    // Cleans up the screen when the app is dropped.
+    output: Terminal<Guard<W>>,
+
+    // When set, frames are drawn by this renderer instead of being written to the output.
+    renderer: Option<Box<dyn Renderer>>,
+
+    // The size of the screen as columns and rows. When it isn't set, the size of the terminal is
+    // used. It's updated with the sizes received from `resizes`.
+    size: Option<(u16, u16)>,
+
+    // Receives the new size of the screen when it's resized. When it isn't set, the app follows
+    // the size of the terminal.
+    resizes: Option<Receiver<(u16, u16)>>,
     state: Arc<Mutex<state::State>>,
+
+    // The last query that compiled into a valid regex. While the user is typing, the query is
+    // often temporarily invalid, e.g. right after typing a `(`. In that case the previous regex
+    // is used and `invalid_regex` is set, so the footer can show an error indicator.
+    regex: Option<Matcher>,
+    invalid_regex: bool,
+
+    // The patterns that `regex` was last compiled from, valid or not.
+    patterns: Option<Vec<String>>,
+
+    // The compiled regex of the last search, used for jumping between matches.
+    search: Option<(Vec<String>, Matcher)>,
+
+    // The number of lines matching the query, if there is a query, and what it was counted for.
+    // It isn't kept when counting ran out of time.
+    match_count: Option<usize>,
+    counted: Option<Scan>,
+
+    // What the matches of the last search, which are kept in the state, were found for.
+    found: Option<Scan>,
+
+    // Counts the changes to `raw_buffer`. What a redraw finds in the buffer is kept together with
+    // the generation it was found in, so an unchanged buffer isn't scanned again, e.g. when only
+    // the spinner turns.
+    generation: u64,
+
+    // The repeated blocks in the buffer and the generation they were found in.
+    blocks: Option<(u64, Vec<Block>)>,
+
+    // The lines that take up a row, from the most recent one to the oldest one, and what they
+    // were picked for.
+    shown: Option<(Layout, Vec<Row>)>,
+
+    // How long highlighting may take during a redraw. When it takes longer, the rest of the
+    // lines are drawn without highlights and `too_slow` is set, so the footer can warn about
+    // it.
+    highlight_budget: Duration,
+    too_slow: bool,
+
+    // The number of the line at the bottom of the screen, if any line is shown.
+    bottom_line: Option<usize>,
+
+    // Matches log levels, like `ERROR`.
+    levels: Regex,
+
+    // Matches lines that are errors.
+    errors: Regex,
+
+    // The regex of replace mode, and the pattern it was compiled from.
+    replace: Option<(String, Regex)>,
+
+    // The regex of the lines that are hidden, and the pattern it was compiled from.
+    exclude: Option<(String, Regex)>,
+
+    // The pinned queries whose matches are highlighted, and the matchers that were compiled for
+    // them.
+    pinned: Vec<(Vec<String>, Matcher)>,
+
+    clipboard: Clipboard,
 }
 
 impl<W> App<W>
 where
     W: io::Write,
 {
-    ///
     pub fn new(output: W) -> Self {
         App {
-            raw_buffer: Arc::new(Mutex::new(Vec::new())),
-            output: output,
+            raw_buffer: Arc::new(Mutex::new(VecDeque::new())),
+            line_transform: None,
+            search_original: false,
+            originals: VecDeque::new(),
+            arrivals: VecDeque::new(),
+            started: SystemTime::now(),
+            live: true,
+            streaming: false,
+            spinner: 0,
+            input: None,
+            max_lines: None,
+            tail: None,
+            quiet_start: false,
+            tab_width: 8,
+            search_prompt: "/".to_string(),
+            command_prompt: ":".to_string(),
+            theme: Theme::default(),
+            history_file: None,
+            warnings: Vec::new(),
+            output: Terminal::new(Guard::new(output)),
+            renderer: None,
+            size: None,
+            resizes: None,
             state: Arc::new(Mutex::new(state::State::new())),
+            regex: None,
+            invalid_regex: false,
+            patterns: None,
+            search: None,
+            match_count: None,
+            counted: None,
+            found: None,
+            generation: 0,
+            blocks: None,
+            shown: None,
+            highlight_budget: DEFAULT_HIGHLIGHT_BUDGET,
+            too_slow: false,
+            bottom_line: None,
+            pinned: Vec::new(),
+            replace: None,
+            exclude: None,
+            clipboard: Clipboard::new(),
+            errors: Regex::new(DEFAULT_ERROR_PATTERN).unwrap(),
+            levels: Regex::new(r"\b(ERROR|WARN|WARNING|INFO|DEBUG)\b").unwrap(),
+        }
+    }
+
+    // Read lines from the given source instead of from STDIN.
+    pub fn source<S: LineSource + 'static>(mut self, source: S) -> Self {
+        self.input = Some(Box::new(source));
+        self
+    }
+
+    // Read lines from the given input instead of from STDIN.
+    pub fn input<R: BufRead + Send + 'static>(self, input: R) -> Self {
+        self.source(Reader(input))
+    }
+
+    // Read lines from the file at the given path instead of from STDIN. Files ending in `.gz` are
+    // decompressed while they're read.
+    pub fn open<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        self.live = false;
+
+        let gzip = path
+            .as_ref()
+            .extension()
+            .is_some_and(|extension| extension == "gz");
+        let file = File::open(path).map_err(AppError::ReadError)?;
+        if gzip {
+            return Ok(self.gzip_input(file));
+        }
+
+        Ok(self.input(BufReader::new(file)))
+    }
+
+    // Read lines from an async stream instead of from STDIN.
+    #[cfg(feature = "async")]
+    pub fn stream<S>(self, stream: S) -> Self
+    where
+        S: futures::Stream<Item = String> + Unpin + Send + 'static,
+    {
+        self.source(input::StreamSource(stream))
+    }
+
+    // Read lines from gzip compressed input. A stream that is corrupt or cut off results in a
+    // `ReadError`.
+    pub fn gzip_input<R: Read + Send + 'static>(self, input: R) -> Self {
+        self.input(BufReader::new(MultiGzDecoder::new(input)))
+    }
+
+    // Limit the number of lines that are kept in memory. By default all lines are kept.
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines);
+        self
+    }
+
+    // Start with only the last lines of the input, like `tail -n <lines> -f` does. The input has
+    // caught up when no new line has come in for a moment. Lines that come in after that are all
+    // kept, unless `max_lines` is set.
+    pub fn tail(mut self, lines: usize) -> Self {
+        self.tail = Some(lines);
+        self
+    }
+
+    // Transform every line before it's added to the buffer. The transformation should return a
+    // single line, without a line ending.
+    pub fn line_transform<F: Fn(&str) -> String + 'static>(mut self, transform: F) -> Self {
+        self.line_transform = Some(Box::new(transform));
+        self
+    }
+
+    // Match queries against the lines as they were read, instead of against the transformed
+    // lines. It's off by default, and it doesn't make a difference without a transformation.
+    pub fn search_original(mut self, search_original: bool) -> Self {
+        self.search_original = search_original;
+        self
+    }
+
+    // Don't draw anything until the input has been read completely, or until a key is pressed.
+    // That saves redrawing while a lot of input is loaded at once. It's off by default.
+    pub fn quiet_start(mut self, quiet_start: bool) -> Self {
+        self.quiet_start = quiet_start;
+        self
+    }
+
+    // Set how long highlighting the lines on the screen may take during a redraw. The default is
+    // 100 milliseconds.
+    pub fn highlight_budget(mut self, budget: Duration) -> Self {
+        self.highlight_budget = budget;
+        self
+    }
+
+    // Set the number of columns between tab stops, the default is 8.
+    pub fn tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width;
+        self
+    }
+
+    // Set what is shown in front of the query in search mode, the default is `/`.
+    pub fn search_prompt(mut self, prompt: &str) -> Self {
+        self.search_prompt = prompt.to_string();
+        self
+    }
+
+    // Set what is shown in front of the command in command mode and in front of the line number
+    // in goto mode, the default is `:`.
+    pub fn command_prompt(mut self, prompt: &str) -> Self {
+        self.command_prompt = prompt.to_string();
+        self
+    }
+
+    // Set the colors that are used for drawing.
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    // With smart case enabled, searching is case insensitive as long as the query doesn't contain
+    // uppercase characters.
+    pub fn smart_case(self, smart_case: bool) -> Self {
+        self.state.lock().unwrap().options.smart_case = smart_case;
+        self
+    }
+
+    // Start in search mode, with the given query already typed in.
+    pub fn query(self, query: &str) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.mode = state::Mode::Search;
+            state.query = query.chars().collect();
+            state.cursor = state.query.len();
+        }
+        self
+    }
+
+    // Apply the options from a configuration file.
+    pub fn config(mut self, config: &Config) -> Self {
+        let theme = &mut self.theme;
+        theme.match_fg = config.match_color.map(AnsiValue).or(theme.match_fg);
+        theme.match_bg = config.match_background.map(AnsiValue).or(theme.match_bg);
+        theme.match_bold = config.match_bold.unwrap_or(theme.match_bold);
+        theme.match_underline = config.match_underline.unwrap_or(theme.match_underline);
+        theme.match_reverse = config.match_reverse.unwrap_or(theme.match_reverse);
+        theme.footer_fg = config.footer_color.map(AnsiValue).or(theme.footer_fg);
+        theme.footer_bg = config.footer_background.map(AnsiValue).or(theme.footer_bg);
+        theme.footer_invert = config.footer_invert.unwrap_or(theme.footer_invert);
+        theme.cursor_line_bg = config
+            .cursor_line_background
+            .map(AnsiValue)
+            .or(theme.cursor_line_bg);
+        self.max_lines = config.max_lines.or(self.max_lines);
+        self.tab_width = config.tab_width.unwrap_or(self.tab_width);
+        if let Some(ref prompt) = config.search_prompt {
+            self.search_prompt = prompt.clone();
+        }
+        if let Some(ref prompt) = config.command_prompt {
+            self.command_prompt = prompt.clone();
+        }
+        self.quiet_start = config.quiet_start.unwrap_or(self.quiet_start);
+        self.highlight_budget = config
+            .highlight_budget
+            .map(Duration::from_millis)
+            .unwrap_or(self.highlight_budget);
+        if let Some(errors) = config
+            .error_pattern
+            .as_ref()
+            .and_then(|p| Regex::new(p).ok())
+        {
+            self.errors = errors;
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.options.case_insensitive = config
+                .case_insensitive
+                .unwrap_or(state.options.case_insensitive);
+            state.options.smart_case = config.smart_case.unwrap_or(state.options.smart_case);
+            state.wrap = config.wrap.unwrap_or(state.wrap);
+            state.show_line_numbers = config.line_numbers.unwrap_or(state.show_line_numbers);
+            state.timestamps = config.timestamps.unwrap_or(state.timestamps);
+            state.highlight_levels = config.highlight_levels.unwrap_or(state.highlight_levels);
+            state.wrap_search = config.wrap_search.unwrap_or(state.wrap_search);
+            state.scroll_step = config.scroll_step.unwrap_or(state.scroll_step);
+            state.context = config.context.unwrap_or(state.context);
+            state.placement = config.placement.unwrap_or(state.placement);
+            if let Some(anchor) = config.anchor {
+                state.set_anchor(anchor);
+            }
+            if !config.bind.is_empty() {
+                let keymap = mem::take(&mut state.keymap);
+                state.keymap = config.keymap(keymap);
+            }
         }
+        self
+    }
+
+    // Use a different regex for finding errors. Lines matching it are errors.
+    pub fn error_pattern(mut self, pattern: &str) -> Result<Self> {
+        self.errors = Regex::new(pattern)?;
+        Ok(self)
+    }
+
+    // Anchor the lines to the top or the bottom of the screen. By default they're anchored to the
+    // bottom and the view follows the input. Anchored to the top, the view starts at the first
+    // line instead.
+    pub fn anchor(self, anchor: Anchor) -> Self {
+        self.state.lock().unwrap().set_anchor(anchor);
+        self
+    }
+
+    // Set the number of lines that Up and Down scroll, the default is 1.
+    pub fn scroll_step(self, lines: usize) -> Self {
+        self.state.lock().unwrap().scroll_step = lines;
+        self
+    }
+
+    // Set where lines that are jumped to, like matches, end up on the screen. By default they're
+    // centered.
+    pub fn placement(self, placement: Placement) -> Self {
+        self.state.lock().unwrap().placement = placement;
+        self
+    }
+
+    // Set whether jumping past the last match continues at the first match. It's enabled by
+    // default.
+    pub fn wrap_search(self, wrap_search: bool) -> Self {
+        self.state.lock().unwrap().wrap_search = wrap_search;
+        self
+    }
+
+    // Show this many lines before and after every match in filter mode, like `grep -C` does.
+    // It's 0 by default.
+    pub fn context(self, lines: usize) -> Self {
+        self.state.lock().unwrap().context = lines;
+        self
+    }
+
+    // Color log levels, like `ERROR` and `INFO`, by their severity. It's disabled by default.
+    pub fn highlight_levels(self, highlight_levels: bool) -> Self {
+        self.state.lock().unwrap().highlight_levels = highlight_levels;
+        self
+    }
+
+    // Use the given key bindings instead of the default ones.
+    pub fn keymap(self, keymap: KeyMap) -> Self {
+        self.state.lock().unwrap().keymap = keymap;
+        self
+    }
+
+    // Start with wrapping of long lines enabled or disabled. It's disabled by default.
+    pub fn wrap(self, wrap: bool) -> Self {
+        self.state.lock().unwrap().wrap = wrap;
+        self
+    }
+
+    // Start with line numbers shown or hidden. They're hidden by default.
+    pub fn line_numbers(self, show_line_numbers: bool) -> Self {
+        self.state.lock().unwrap().show_line_numbers = show_line_numbers;
+        self
+    }
+
+    // Start with the gutter showing when lines were read. Timestamps are off by default.
+    pub fn timestamps(self, timestamps: Timestamps) -> Self {
+        self.state.lock().unwrap().timestamps = timestamps;
+        self
+    }
+
+    // Start with collapsing of consecutive identical lines enabled or disabled. It's disabled by
+    // default.
+    pub fn dedup(self, dedup: bool) -> Self {
+        self.state.lock().unwrap().dedup = dedup;
+        self
+    }
+
+    // Whether the view follows new input, which it does by default. When it doesn't, the view
+    // stays put while new lines come in, until the user jumps to the bottom.
+    pub fn follow(self, following: bool) -> Self {
+        self.state.lock().unwrap().following = following;
+        self
+    }
+
+    // Draw the screen with the given renderer instead of writing it to the output, e.g. to record
+    // the frames.
+    pub fn renderer<R: Renderer + 'static>(mut self, renderer: R) -> Self {
+        self.renderer = Some(Box::new(renderer));
+        self
+    }
+
+    // Draw the screen in the given number of columns and rows, regardless of the size of the
+    // terminal.
+    pub fn size(mut self, width: u16, height: u16) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    // Resize the screen to the sizes received from the channel, as columns and rows. Without it,
+    // the app follows the size of the terminal, unless a size is set.
+    pub fn resizes(mut self, resizes: Receiver<(u16, u16)>) -> Self {
+        self.resizes = Some(resizes);
+        self
+    }
+
+    // Keep the search history in the given file, e.g. `default_history_path()`. Without it the
+    // history is forgotten when the app stops.
+    pub fn history_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.history_file = Some(path.into());
+        self
+    }
+
+    // Don't load and save the search history.
+    pub fn no_history(mut self) -> Self {
+        self.history_file = None;
+        self
+    }
+
+    // Return the problems that didn't stop the app, like the history that couldn't be saved.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    // Return the lines that have been read so far, the oldest first. They're lent instead of
+    // copied, no lines are added to the buffer until the returned guard is dropped.
+    pub fn lines(&self) -> Lines<'_> {
+        let mut buffer = self.raw_buffer.lock().unwrap();
+        buffer.make_contiguous();
+        Lines(buffer)
+    }
+
+    // Return the query as it has been typed. `query()` is taken by the builder that sets the
+    // query to start with.
+    pub fn current_query(&self) -> String {
+        self.state.lock().unwrap().query.iter().collect()
+    }
+
+    pub fn mode(&self) -> state::Mode {
+        self.state.lock().unwrap().mode
     }
 
     // Return a footer that is as wide as the output is. The footer is a single line that spans
     // the width of the shell.  The query that has been searched for is left on the line, while the
     // current mode is printed at the right corner. It looks something like this.
     //
-    //      <query> .........<mode>
-    fn footer(&self, width: usize) -> String {
+    //      <query> .........<input> <position> <matches> <flags> <mode>
+    //
+    // The position is the number of the line at the bottom of the screen, out of the total number
+    // of lines, like `1234/5678 (22%)`. When the view is at the bottom, `(BOT)` is shown instead of
+    // the percentage.
+    // While the input is still being read, `[streaming]` with a spinner is shown in front of the
+    // position. Once all input has been read, `[done]` is shown there instead.
+    // The flags show which search options are enabled, e.g. `[i]` for case insensitive search
+    // and `[F]` for literal search.
+    // If the query isn't a valid regex, a red `!` is printed in front of the mode.
+    //
+    // When the shell is too narrow to fit everything, the padding is dropped first. After that the
+    // query is trimmed from the left, so the most recently typed characters and the mode stay
+    // visible. The part of the query around the cursor is always visible.
+    //
+    // Besides the footer, the column of the cursor is returned while a query or a command is being
+    // typed.
+    fn footer(&self, width: usize) -> (String, Option<usize>) {
         let mut footer = String::new();
         let state = self.state.clone();
         let state = state.lock().unwrap();
 
-        let mode = &state.mode.to_string();
-        for c in state.query.clone() {
-            footer.push(c);
+        let mut status = String::new();
+        if self.streaming {
+            status.push_str(&format!(
+                "[streaming {}] ",
+                SPINNER[self.spinner % SPINNER.len()]
+            ));
+        } else {
+            status.push_str("[done] ");
+        }
+        if let Some(line) = self.bottom_line {
+            let total = state.buffer_len;
+            if state.scroll_offset == 0 {
+                status.push_str(&format!("{}/{} (BOT)   ", line, total));
+            } else {
+                status.push_str(&format!("{}/{} ({}%)   ", line, total, line * 100 / total));
+            }
+        }
+        if let Some(n) = self.match_count {
+            status.push_str(&format!("{} matches   ", n));
+        }
+        if self.too_slow {
+            status.push_str("(search too slow) ");
+        }
+        if state.options.case_insensitive {
+            status.push_str("[i] ");
+        }
+        if state.options.literal {
+            status.push_str("[F] ");
+        }
+        if state.options.whole_word {
+            status.push_str("[w] ");
+        }
+        if state.options.fuzzy {
+            status.push_str("[fuzzy] ");
+        }
+        if state.options.all_terms {
+            status.push_str("[and] ");
+        }
+        if state.options.anchor_start {
+            status.push_str("[^] ");
+        }
+        if state.options.anchor_end {
+            status.push_str("[$] ");
+        }
+        if state.options.start_column.is_some() || state.options.end_column.is_some() {
+            let column = |column: Option<usize>| column.map(|c| c.to_string()).unwrap_or_default();
+            status.push_str(&format!(
+                "[{}..{}] ",
+                column(state.options.start_column),
+                column(state.options.end_column)
+            ));
+        }
+        if state.filter {
+            status.push_str("[filter] ");
+        }
+        if state.invert {
+            status.push_str("[v] ");
+        }
+        if state.wrap {
+            status.push_str("[wrap] ");
+        }
+        if state.dedup {
+            status.push_str("[dedup] ");
+        }
+        if state.collapse_blocks {
+            status.push_str("[blocks] ");
+        }
+        if !state.exclude.is_empty() && state.mode != state::Mode::Exclude {
+            let exclude: String = state.exclude.iter().collect();
+            status.push_str(&format!("[not {}] ", exclude));
+        }
+        status.push_str(&state.mode.to_string());
+
+        // Everything is measured in columns, wide characters like CJK take up two of them.
+        let mut status = status.as_str();
+        while status.width() > width {
+            let mut chars = status.chars();
+            chars.next();
+            status = chars.as_str();
+        }
+        let mut room = width - status.width();
+
+        let indicator = self.invalid_regex && room >= 2;
+        if indicator {
+            room -= 2;
+        }
+
+        // A message, like an error, replaces the query until the next key is pressed. In command
+        // mode the command that is being typed is shown instead of the query. While the query,
+        // the command or the line number is typed, the prompt of the mode is in front of it.
+        let prompt = match state.mode {
+            state::Mode::Search => self.search_prompt.chars().count(),
+            _ => 0,
+        };
+        let left: Vec<char> = match (&state.message, state.mode) {
+            (Some(message), _) => message.chars().collect(),
+            (None, state::Mode::Search) => self
+                .search_prompt
+                .chars()
+                .chain(state.query.iter().copied())
+                .collect(),
+            (None, state::Mode::Command) => self
+                .command_prompt
+                .chars()
+                .chain(state.command.iter().copied())
+                .collect(),
+            (None, state::Mode::Goto) => self
+                .command_prompt
+                .chars()
+                .chain(state.goto.chars())
+                .collect(),
+            (None, state::Mode::Exclude) => "Exclude: "
+                .chars()
+                .chain(state.exclude.iter().copied())
+                .collect(),
+            (None, state::Mode::Replace) => iter::once('s')
+                .chain(iter::once('/'))
+                .chain(state.replace_pattern.clone())
+                .chain(iter::once('/'))
+                .chain(state.replacement.clone())
+                .collect(),
+            (None, _) => state.query.clone(),
+        };
+
+        let cursor = match (&state.message, state.mode) {
+            (None, state::Mode::Search) => Some(prompt + state.cursor),
+            (None, state::Mode::Command)
+            | (None, state::Mode::Goto)
+            | (None, state::Mode::Exclude) => Some(left.len()),
+            (None, state::Mode::Replace) if !state.editing_replacement => {
+                Some(state.replace_pattern.len() + 2)
+            }
+            (None, state::Mode::Replace) => Some(left.len()),
+            _ => None,
+        };
+
+        // Trim the left side from the left until it fits. At the end of the query, the cursor
+        // needs a column of its own. If the cursor is further to the left than that, the left side
+        // starts at the cursor and is trimmed from the right instead.
+        let columns =
+            |chars: &[char]| -> usize { chars.iter().map(|c| c.width().unwrap_or(0)).sum() };
+        let extra = match cursor {
+            Some(cursor) if cursor == left.len() => 1,
+            _ => 0,
+        };
+        let mut start = 0;
+        while start < left.len() && columns(&left[start..]) + extra > room {
+            start += 1;
+        }
+        let mut end = left.len();
+        if let Some(cursor) = cursor {
+            if cursor < start {
+                start = cursor;
+                while columns(&left[start..end]) > room {
+                    end -= 1;
+                }
+            }
+        }
+        let cursor = cursor
+            .map(|cursor| columns(&left[start..cursor]))
+            .filter(|&column| column < room);
+
+        let left = &left[start..end];
+        for c in left {
+            footer.push(*c);
         }
 
-        let padding = vec![' '; width - state.query.len() - mode.chars().count() - 1];
-        for c in padding {
-            footer.push(c);
+        footer.push_str(&" ".repeat(room - columns(left)));
+
+        // Resetting the color of the indicator resets the color of the footer too, so it's set
+        // again.
+        if indicator {
+            footer.push_str(&format!(
+                "{}!{}{} ",
+                color::Fg(color::Red),
+                color::Fg(color::Reset),
+                self.theme.footer_start()
+            ));
         }
 
-        footer.push_str(mode);
+        footer.push_str(status);
 
-        footer
+        (footer, cursor)
     }
 
-    pub fn start(&mut self, keys: Receiver<Key>) -> Result<()> {
-        write!(self.output, "{}", clear::All)?;
-        self.output.flush()?;
+    pub fn start(&mut self, events: Receiver<Event>) -> Result<()> {
+        let history = match self.history_file {
+            Some(ref path) => history::load(path),
+            None => Vec::new(),
+        };
+        let loaded = history.len();
+        self.state.lock().unwrap().history = history;
 
-        let state = self.state.clone();
-        let t = thread::spawn(move || loop {
-            select! {
-                    recv(keys) -> key => {
-                        match key {
-                            Ok(key) => {
-                                if key == Key::Ctrl('c') {
-                                    return
-                                }
-                                state.lock().unwrap().process_key(key);
-                            },
-                            Err(e) => {
-                                return
-                            }
+        // When the output has been closed, e.g. the terminal is gone, there's nobody left to
+        // show anything to. That isn't an error, the app just stops.
+        let result = match self.run(events) {
+            Err(AppError::WriteError(ref e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        };
+
+        // Only the queries of this session are added, so that multiple sessions running at
+        // the same time don't duplicate each other's queries. Losing the history is no reason
+        // to fail.
+        if let Some(ref path) = self.history_file {
+            let state = self.state.lock().unwrap();
+            if let Err(e) = history::append(path, &state.history[loaded..]) {
+                self.warnings.push(format!(
+                    "Failed to save the search history to {}: {}",
+                    path.display(),
+                    e
+                ));
+            }
+        }
+
+        result
+    }
+
+    fn run(&mut self, events: Receiver<Event>) -> Result<()> {
+        self.started = SystemTime::now();
+        self.output.get_mut().activate();
+        write!(self.output, "{}", terminal::start_sequence()).map_err(AppError::WriteError)?;
+        self.output.flush().map_err(AppError::WriteError)?;
+
+        let mut source = self
+            .input
+            .take()
+            .unwrap_or_else(|| Box::new(Reader(BufReader::new(io::stdin()))));
+
+        // Input is read on a separate thread, so the user can search while lines are still coming
+        // in. Reaching the end of the input stops reading, but the user can keep on scrolling and
+        // searching.
+        let (sender, mut lines) = channel::unbounded();
+        self.streaming = true;
+        thread::spawn(move || loop {
+            match source.next_line() {
+                Ok(Some(line)) => {
+                    if sender.send(Ok((line, SystemTime::now()))).is_err() {
+                        return;
                     }
                 }
+                Ok(None) => return,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
             }
         });
 
-        let raw_buffer = self.raw_buffer.clone();
-        thread::spawn(move || {
-            let input = io::stdin();
-            let mut input = input.lock();
+        // Lines often come in faster than the screen can be redrawn. Instead of redrawing for every
+        // line, the screen is redrawn at most once every `REDRAW_INTERVAL` while lines come in.
+        // Key presses are handled right away.
+        let mut redraw = channel::never();
+        let mut pending = false;
 
-            loop {
-                let mut line = String::new();
-                let n = input.read_line(&mut line).expect("Failed to read input");
+        // While only the tail of the input is kept, the input has caught up once this fires.
+        let mut caught_up = channel::never();
 
-                if n == 0 {
-                    return;
-                }
+        // Turns the spinner, until the input has been read.
+        let mut spinner = channel::tick(SPINNER_INTERVAL);
 
-                raw_buffer.lock().unwrap().push(line);
+        let mut resizes = match (self.resizes.take(), self.size) {
+            (Some(resizes), _) => resizes,
+            (None, None) => terminal::resizes(),
+            (None, Some(_)) => channel::never(),
+        };
+
+        let mut quiet = self.quiet_start;
+        if !quiet {
+            self.redraw()?;
+        }
+        loop {
+            select! {
+                recv(events) -> event => {
+                    match event {
+                        Ok(Event::Key(Key::Ctrl('c'))) | Err(_) => return Ok(()),
+                        Ok(event) => {
+                            quiet = false;
+                            self.state.lock().unwrap().process_event(event)?;
+                            if self.state.lock().unwrap().quit {
+                                return Ok(());
+                            }
+                            if mem::take(&mut self.state.lock().unwrap().save) {
+                                let message = match self.save() {
+                                    Ok((path, n)) => {
+                                        format!("Saved {} lines to {}", n, path.display())
+                                    }
+                                    Err(e) => format!("Failed to save: {}", e),
+                                };
+                                self.state.lock().unwrap().message = Some(message);
+                            }
+                            let copy = self.state.lock().unwrap().copy.take();
+                            if let Some(target) = copy {
+                                let message = self.copy(target)?;
+                                self.state.lock().unwrap().message = Some(message);
+                            }
+                            self.redraw()?;
+                            redraw = channel::never();
+                            pending = false;
+                        }
+                    }
+                }
+                recv(lines) -> line => {
+                    match line {
+                        Ok(Ok((line, arrival))) => {
+                            let arrival = if self.live { Some(arrival) } else { None };
+                            self.push_line(&line, arrival);
+                            if self.tail.is_some() {
+                                caught_up = channel::after(REDRAW_INTERVAL);
+                            }
+                            if !pending && !quiet {
+                                redraw = channel::after(REDRAW_INTERVAL);
+                                pending = true;
+                            }
+                        }
+                        Ok(Err(e)) => return Err(e),
+                        // The input has been read completely.
+                        Err(_) => {
+                            lines = channel::never();
+                            spinner = channel::never();
+                            self.streaming = false;
+                            self.tail = None;
+                            self.redraw()?;
+                            redraw = channel::never();
+                            pending = false;
+                        }
+                    }
+                }
+                recv(resizes) -> size => {
+                    match size {
+                        // The scroll offset is clamped to what fits on the new screen when it's
+                        // redrawn.
+                        Ok(size) => {
+                            self.size = Some(size);
+                            self.output.invalidate();
+                            self.redraw()?;
+                            redraw = channel::never();
+                            pending = false;
+                        }
+                        // Resizes can't be noticed.
+                        Err(_) => resizes = channel::never(),
+                    }
+                }
+                recv(spinner) -> _ => {
+                    self.spinner += 1;
+                    if !pending && !quiet {
+                        redraw = channel::after(REDRAW_INTERVAL);
+                        pending = true;
+                    }
+                }
+                recv(caught_up) -> _ => {
+                    self.tail = None;
+                    caught_up = channel::never();
+                }
+                recv(redraw) -> _ => {
+                    self.redraw()?;
+                    redraw = channel::never();
+                    pending = false;
+                }
             }
-        });
+        }
+    }
+
+    // Read all input and write the lines that match the query to the output, without any
+    // interaction. With `highlight` set, the parts of the lines that matched are colored.
+    pub fn run_batch(
+        &mut self,
+        query: &str,
+        options: SearchOptions,
+        highlight: bool,
+    ) -> Result<()> {
+        self.read_all()?;
+
+        let raw_buffer = self.raw_buffer.clone();
+        let mut buffer = raw_buffer.lock().unwrap();
+        let lines = buffer.make_contiguous();
 
-        t.join();
-        return Ok(());
+        // Matches in the original lines can't be highlighted in the transformed lines.
+        let originals = self.originals.make_contiguous();
+        let (searched, highlight) = if originals.is_empty() {
+            (&*lines, highlight)
+        } else {
+            (&*originals, false)
+        };
+        for m in search::find_matches(searched, query, options) {
+            let spans = if highlight { &m.spans[..] } else { &[] };
+            write_highlighted(&mut self.output, &lines[m.line], spans, &self.theme)
+                .map_err(AppError::WriteError)?;
+        }
+        self.output.flush().map_err(AppError::WriteError)?;
+
+        Ok(())
     }
 
-    fn redraw(&mut self) -> Result<()> {
-        let (width, height) = terminal_size().unwrap();
-        write!(self.output, "{}", clear::All)?;
-        self.output.flush()?;
-        let state = self.state.clone();
+    // Read all input and write the lines that match the query to the output as JSON, one object
+    // per line, for other tools to process. Lines that don't match aren't written at all. An
+    // object looks like this:
+    //
+    //     {"line_number":12,"line":"an error","spans":[[3,8]]}
+    //
+    // The spans are the byte ranges of the line that matched. With `search_original` set, the
+    // line is the original line, which is what the spans apply to.
+    pub fn run_json(&mut self, query: &str, options: SearchOptions) -> Result<()> {
+        self.read_all()?;
+
         let raw_buffer = self.raw_buffer.clone();
+        let mut buffer = raw_buffer.lock().unwrap();
+        let lines = buffer.make_contiguous();
+        let originals = self.originals.make_contiguous();
+        let searched = if originals.is_empty() {
+            &*lines
+        } else {
+            &*originals
+        };
 
-        let mut regex = String::new();
-        for c in state.lock().unwrap().query.clone() {
-            regex.push(c);
+        for m in search::find_matches(searched, query, options) {
+            let json = JsonMatch {
+                line_number: m.line + 1,
+                line: &searched[m.line],
+                spans: m.spans.iter().map(|span| (span.start, span.end)).collect(),
+            };
+            serde_json::to_writer(&mut self.output, &json)
+                .map_err(|e| AppError::WriteError(e.into()))?;
+            writeln!(self.output).map_err(AppError::WriteError)?;
         }
+        self.output.flush().map_err(AppError::WriteError)?;
 
-        let regex = format!(r"(.*)(?P<m>{})(.*)", regex);
-        let re = Regex::new(&regex.as_str()).unwrap();
+        Ok(())
+    }
 
-        for (i, line) in raw_buffer.lock().unwrap().iter().rev().enumerate() {
-            if re.is_match(line) {
-                if i >= height as usize {
-                    break;
-                }
+    // Read the input until it ends.
+    fn read_all(&mut self) -> Result<()> {
+        let mut source = self
+            .input
+            .take()
+            .unwrap_or_else(|| Box::new(Reader(BufReader::new(io::stdin()))));
+        while let Some(line) = source.next_line()? {
+            self.push_line(&line, None);
+        }
+
+        Ok(())
+    }
+
+    // Save the lines that are shown to a new file in the working directory. Returns the path of
+    // the file and the number of lines that were saved. An existing file is never overwritten,
+    // when a file with the name already exists a counter is added to the name.
+    fn save(&mut self) -> Result<(PathBuf, usize)> {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
 
-                for cap in re.captures_iter(line) {
-                    write!(
-                        self.output,
-                        "{}{}{}{}{}{}",
-                        termion::cursor::Goto(1, height - i as u16),
-                        &cap[1],
-                        color::Fg(color::Red),
-                        &cap[2],
-                        color::Fg(color::Reset),
-                        &cap[3],
-                    )?;
+        let mut attempt = 0;
+        let (path, file) = loop {
+            let path = match attempt {
+                0 => PathBuf::from(format!("kurzhozen-{}.log", since_epoch.as_secs())),
+                n => PathBuf::from(format!("kurzhozen-{}-{}.log", since_epoch.as_secs(), n)),
+            };
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(AppError::WriteError(e)),
+            }
+        };
+
+        let mut file = BufWriter::new(file);
+        let n = self.write_shown(&mut file)?;
+        file.flush().map_err(AppError::WriteError)?;
+        Ok((path, n))
+    }
+
+    // Copy the line or the query to the clipboard. Returns the message to show the user.
+    fn copy(&mut self, target: CopyTarget) -> Result<String> {
+        let text = {
+            let state = self.state.lock().unwrap();
+            match target {
+                CopyTarget::Line => state
+                    .cursor_line
+                    .or(state.current_match)
+                    .or_else(|| self.bottom_line.map(|number| number - 1))
+                    .and_then(|line| self.raw_buffer.lock().unwrap().get(line).cloned()),
+                CopyTarget::Query => {
+                    Some(state.query.iter().collect::<String>()).filter(|query| !query.is_empty())
                 }
+            }
+        };
+
+        let text = match text {
+            Some(text) => text,
+            None => return Ok("Nothing to copy".to_string()),
+        };
+        self.clipboard
+            .copy(&text, &mut self.output)
+            .map_err(AppError::WriteError)?;
+
+        Ok(match target {
+            CopyTarget::Line => "Copied the line".to_string(),
+            CopyTarget::Query => "Copied the query".to_string(),
+        })
+    }
+
+    // Write the lines that are shown to the output, one per line. In filter mode only the lines
+    // matching the query are written, when the search is inverted only the lines that don't.
+    // Returns the number of lines that were written.
+    fn write_shown<O: io::Write>(&mut self, output: &mut O) -> Result<usize> {
+        // Both lock the state, so they go first.
+        let replace = self.replace();
+        let exclude = self.exclude();
+
+        let state = self.state.lock().unwrap();
+        let regex = if state.patterns().is_empty() {
+            None
+        } else {
+            self.regex.as_ref()
+        };
+        let filter = state.filter && regex.is_some();
+        let invert = state.invert && regex.is_some();
+
+        let mut n = 0;
+        for (i, line) in self.raw_buffer.lock().unwrap().iter().enumerate() {
+            let searched = self.originals.get(i).unwrap_or(line);
+            let matches = regex.is_some_and(|re| re.is_match(searched));
+            if !shown(matches, filter, invert)
+                || exclude.as_ref().is_some_and(|re| re.is_match(searched))
+            {
                 continue;
             }
 
-            write!(
-                self.output,
-                "{}{}",
-                termion::cursor::Goto(1, height - i as u16),
-                line
-            )
-            .unwrap()
+            match replace {
+                Some((ref re, ref replacement)) => {
+                    writeln!(output, "{}", substitute(re, replacement, line).0)
+                }
+                None => writeln!(output, "{}", line),
+            }
+            .map_err(AppError::WriteError)?;
+            n += 1;
         }
-        self.output.flush().unwrap();
-        let footer = self.footer(width as usize);
+        Ok(n)
+    }
 
-        write!(
-            self.output,
-            "{}{}{}{}",
-            termion::cursor::Goto(1, height),
-            style::Invert,
-            footer,
-            style::Reset
-        )?;
-        self.output.flush()?;
+    // Return the regex and the replacement while in replace mode, if the regex is valid. Like the
+    // query, the regex is only compiled again when it has changed.
+    fn replace(&mut self) -> Option<(Regex, String)> {
+        let state = self.state.lock().unwrap();
+        if state.mode != state::Mode::Replace {
+            return None;
+        }
 
-        Ok(())
+        let pattern: String = state.replace_pattern.iter().collect();
+        let replacement: String = state.replacement.iter().collect();
+        if self.replace.as_ref().map(|(p, _)| p) != Some(&pattern) {
+            self.replace = Regex::new(&pattern).ok().map(|re| (pattern, re));
+        }
+
+        match self.replace {
+            Some((ref pattern, ref re)) if !pattern.is_empty() => Some((re.clone(), replacement)),
+            _ => None,
+        }
+    }
+
+    // Return the regex of the lines that are hidden, if lines are hidden and the regex is valid.
+    // Like the query, the regex is only compiled again when it has changed.
+    fn exclude(&mut self) -> Option<Regex> {
+        let pattern: String = self.state.lock().unwrap().exclude.iter().collect();
+        if self.exclude.as_ref().map(|(p, _)| p) != Some(&pattern) {
+            self.exclude = Regex::new(&pattern).ok().map(|re| (pattern, re));
+        }
+
+        match self.exclude {
+            Some((ref pattern, ref re)) if !pattern.is_empty() => Some(re.clone()),
+            _ => None,
+        }
+    }
+
+    // Clean up a line that has been read from the input and add it to the buffer, together with
+    // the time it was read.
+    fn push_line(&mut self, line: &str, arrival: Option<SystemTime>) {
+        let line = input::expand_tabs(
+            &input::strip_ansi(input::trim_line_ending(line)),
+            self.tab_width,
+        );
+        let mut buffer = self.raw_buffer.lock().unwrap();
+        self.generation += 1;
+        match self.line_transform {
+            Some(ref transform) => {
+                buffer.push_back(transform(&line));
+                if self.search_original {
+                    self.originals.push_back(line);
+                }
+            }
+            None => buffer.push_back(line),
+        }
+        self.arrivals.push_back(arrival);
+
+        let mut state = self.state.lock().unwrap();
+        let limit = match (self.max_lines, self.tail) {
+            (Some(max_lines), Some(tail)) => Some(cmp::min(max_lines, tail)),
+            (max_lines, tail) => max_lines.or(tail),
+        };
+        if let Some(limit) = limit {
+            if buffer.len() > limit {
+                buffer.pop_front();
+                self.originals.pop_front();
+                self.arrivals.pop_front();
+
+                // All lines moved up by one, so the indices of the current match and the marks
+                // change as well.
+                state.drop_first_line();
+            }
+        }
+
+        let searched = self.originals.back().or(buffer.back()).unwrap();
+        if self.errors.is_match(searched) {
+            state.error_lines.push(buffer.len() - 1);
+        }
+
+        // The view is kept in place on the next redraw, when the user isn't following the input.
+        state.new_lines += 1;
+    }
+
+    fn redraw(&mut self) -> Result<()> {
+        let (width, height) = match self.size {
+            Some(size) => size,
+            None => terminal_size().unwrap_or(DEFAULT_SIZE),
+        };
+
+        // Without any rows there's nothing to draw. With a single row, only the footer is drawn.
+        if height == 0 {
+            return Ok(());
+        }
+
+        // The last row is the footer, lines are drawn on the rows above it.
+        let content_rows = height as usize - 1;
+        let state = self.state.clone();
+        let raw_buffer = self.raw_buffer.clone();
+
+        // Compiling a regex is expensive, so only do it when the query has changed.
+        let patterns = state.lock().unwrap().patterns();
+        if self.patterns.as_ref() != Some(&patterns) {
+            match Matcher::new(&patterns) {
+                Ok(re) => {
+                    self.regex = Some(re);
+                    self.invalid_regex = false;
+                }
+                Err(_) => self.invalid_regex = true,
+            }
+            self.patterns = Some(patterns.clone());
+        }
+
+        // After leaving search mode, the last search is kept around so the user can jump between
+        // its matches.
+        let search = state.lock().unwrap().search.clone();
+        if self.search.as_ref().map(|(pattern, _)| pattern) != search.as_ref() {
+            self.search = search.and_then(|patterns| match Matcher::new(&patterns) {
+                Ok(matcher) => Some((patterns, matcher)),
+                Err(_) => None,
+            });
+        }
+
+        // The column range can change without the query changing.
+        let (start_column, end_column) = {
+            let state = state.lock().unwrap();
+            (state.options.start_column, state.options.end_column)
+        };
+        if let Some(ref mut re) = self.regex {
+            re.set_columns(start_column, end_column);
+        }
+        if let Some((_, ref mut matcher)) = self.search {
+            matcher.set_columns(start_column, end_column);
+        }
+
+        let replace = self.replace();
+        let exclude = self.exclude();
+
+        // Pinned queries have been valid when they were pinned, so they compile.
+        let pinned = state.lock().unwrap().pinned.clone();
+        if self
+            .pinned
+            .iter()
+            .map(|(patterns, _)| patterns)
+            .ne(pinned.iter())
+        {
+            self.pinned = pinned
+                .into_iter()
+                .filter_map(|patterns| {
+                    let matcher = Matcher::new(&patterns).ok()?;
+                    Some((patterns, matcher))
+                })
+                .collect();
+        }
+
+        // An empty regex matches every line, but an empty query should highlight nothing and
+        // filter nothing.
+        let empty_query = patterns.is_empty();
+        let filter = state.lock().unwrap().filter && !empty_query;
+        let invert = state.lock().unwrap().invert && !empty_query;
+        let wrap = state.lock().unwrap().wrap;
+        let show_line_numbers = state.lock().unwrap().show_line_numbers;
+        let timestamps = state.lock().unwrap().timestamps;
+        let dedup = state.lock().unwrap().dedup;
+        let collapse_blocks = state.lock().unwrap().collapse_blocks;
+        let whitespace = state.lock().unwrap().show_whitespace;
+        let context = match filter && !invert {
+            true => state.lock().unwrap().context,
+            false => 0,
+        };
+        let buffer = raw_buffer.lock().unwrap();
+
+        // The lines that queries are matched against, see `search_original`.
+        let searched = if self.originals.is_empty() {
+            &*buffer
+        } else {
+            &self.originals
+        };
+
+        // The gutter with timestamps fits the timestamp of the most recent line, which is the
+        // widest one.
+        let stamp_width = self
+            .arrivals
+            .iter()
+            .rev()
+            .flatten()
+            .next()
+            .and_then(|&arrival| timestamp(timestamps, arrival, self.started))
+            .map_or(0, |stamp| stamp.len() + 1);
+
+        // A collapsed block is shown once, the repetitions are hidden. The last line of the block
+        // gets a counter with the number of times the block is repeated.
+        if collapse_blocks
+            && self.blocks.as_ref().map(|(generation, _)| *generation) != Some(self.generation)
+        {
+            self.blocks = Some((self.generation, find_blocks(&buffer)));
+        }
+        let blocks: &[Block] = match self.blocks {
+            Some((_, ref blocks)) if collapse_blocks => blocks,
+            _ => &[],
+        };
+        let mut markers = HashMap::new();
+        let expanded_blocks = {
+            let mut state = state.lock().unwrap();
+            for (lines, size) in blocks {
+                if !state.expanded_blocks.contains(&lines.start) {
+                    markers.insert(lines.start + size - 1, lines.len() / size);
+                }
+            }
+            state.blocks = blocks.iter().map(|(lines, _)| lines.clone()).collect();
+            state.expanded_blocks.clone()
+        };
+
+        // Matching can be slow for complicated queries, on long lines in particular. Counting the
+        // matches and highlighting the lines on the screen stop once the budget has been used
+        // up, so the app stays responsive. The remaining lines are drawn as plain text then.
+        let deadline = Instant::now() + self.highlight_budget;
+        let mut too_slow = false;
+
+        // In filter mode, lines that don't match the query don't take up a row. When the search
+        // is inverted, it's the other way around. Otherwise lines may match, that's only checked
+        // when they're highlighted.
+        let regex = if empty_query { &None } else { &self.regex };
+
+        let scan = Scan {
+            generation: self.generation,
+            patterns: Some(patterns).filter(|_| !empty_query),
+            columns: (start_column, end_column),
+        };
+        let layout = Layout {
+            scan: scan.clone(),
+            filter,
+            invert,
+            context,
+            dedup,
+            collapse_blocks,
+            expanded_blocks,
+            exclude: exclude.as_ref().map(|re| re.as_str().to_string()),
+        };
+        let layout_changed = self.shown.as_ref().map(|(layout, _)| layout) != Some(&layout);
+        if layout_changed {
+            let mut hidden = vec![false; if collapse_blocks { buffer.len() } else { 0 }];
+            for (lines, size) in blocks {
+                if !layout.expanded_blocks.contains(&lines.start) {
+                    for hidden in &mut hidden[lines.start + size..lines.end] {
+                        *hidden = true;
+                    }
+                }
+            }
+
+            // Lines are drawn from the bottom of the screen upwards, starting with the most
+            // recent line. In dedup mode, a run of identical lines is folded into the first line
+            // of the run, together with the number of times it's repeated. The buffer itself is
+            // left alone. Lines matching the exclude regex never take up a row.
+            let mut runs: Vec<(usize, usize)> = Vec::new();
+            for (i, line) in buffer.iter().enumerate().rev() {
+                if hidden.get(i) == Some(&true)
+                    || exclude.as_ref().is_some_and(|re| re.is_match(&searched[i]))
+                {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some((first, count)) if dedup && buffer[*first] == *line => {
+                        *first = i;
+                        *count += 1;
+                    }
+                    _ => runs.push((i, 1)),
+                }
+            }
+
+            // With context, the lines around a match are shown in filter mode as well. Groups of
+            // lines that aren't next to each other are separated by a row with `--`.
+            let mut matched = HashSet::new();
+            let mut near = HashSet::new();
+            let mut separated = HashSet::new();
+            if let (Some(re), true) = (regex, context > 0) {
+                let mut previous: Option<usize> = None;
+                for (k, &(i, _)) in runs.iter().enumerate() {
+                    if !re.is_match(&searched[i]) {
+                        continue;
+                    }
+                    matched.insert(i);
+
+                    let window = k.saturating_sub(context)..cmp::min(k + context + 1, runs.len());
+                    if let Some(previous) = previous.filter(|&previous| previous + 1 < window.start)
+                    {
+                        separated.insert(runs[previous].0);
+                    }
+                    near.extend(runs[window.clone()].iter().map(|&(i, _)| i));
+                    previous = Some(window.end - 1);
+                }
+            }
+
+            let shown = runs
+                .into_iter()
+                .map(|(i, count)| match regex {
+                    Some(_) if context > 0 => (i, count, matched.contains(&i)),
+                    Some(re) if filter || invert => (i, count, re.is_match(&searched[i])),
+                    Some(_) => (i, count, true),
+                    None => (i, count, false),
+                })
+                .filter(|&(i, _, matches)| shown(matches, filter, invert) || near.contains(&i))
+                .flat_map(|line| {
+                    let separator =
+                        Some((line.0, 0, false)).filter(|_| separated.contains(&line.0));
+                    iter::once(line).chain(separator)
+                })
+                .collect();
+            self.shown = Some((layout, shown));
+        }
+
+        let separator = String::from("--");
+        let shown: &[Row] = match self.shown {
+            Some((_, ref shown)) => shown,
+            None => &[],
+        };
+        let lines = shown.iter().map(|&(i, count, matches)| {
+            let line = if count == 0 { &separator } else { &buffer[i] };
+            (i, line, count, matches)
+        });
+
+        if self.counted.as_ref() != Some(&scan) {
+            self.match_count = match regex {
+                Some(re) => {
+                    let mut count = 0;
+                    let counted = searched.iter().all(|line| {
+                        if re.is_match(line) {
+                            count += 1;
+                        }
+                        Instant::now() <= deadline
+                    });
+                    too_slow = !counted;
+                    Some(count).filter(|_| counted)
+                }
+                None => None,
+            };
+            self.counted = Some(scan.clone()).filter(|_| !too_slow);
+        }
+
+        // Unless every line has a row of its own, the line that every row shows is kept, so the
+        // state can tell which row a line is on.
+        let rows: Option<Vec<usize>> = match layout_changed {
+            true if filter || invert || dedup || collapse_blocks || exclude.is_some() => {
+                Some(shown.iter().rev().map(|&(i, _, _)| i).collect())
+            }
+            _ => None,
+        };
+        let total = shown.len();
+
+        // The lines that were added since the last redraw move the view up as far as they take
+        // up rows, so the view stays in place when the user isn't following the input.
+        let new_lines = mem::take(&mut state.lock().unwrap().new_lines);
+        let first_new = buffer.len().saturating_sub(new_lines);
+        let new_rows = shown
+            .iter()
+            .take_while(|&&(i, _, _)| i >= first_new)
+            .count();
+
+        // The matches of the last search are only found again when the search or the buffer
+        // changes.
+        let found = Scan {
+            patterns: self.search.as_ref().map(|(patterns, _)| patterns.clone()),
+            ..scan
+        };
+        let matches = match self.search {
+            _ if self.found.as_ref() == Some(&found) => None,
+            Some((_, ref re)) => Some(search::find(re, searched.iter())),
+            None => Some(Vec::new()),
+        };
+        self.found = Some(found);
+        let matches = matches.map(|matches| {
+            let columns = matches
+                .iter()
+                .map(|m| {
+                    m.spans
+                        .iter()
+                        .map(|span| searched[m.line][..span.start].width())
+                        .collect()
+                })
+                .collect();
+            (matches.into_iter().map(|m| m.line).collect(), columns)
+        });
+
+        // The scroll offset is the number of rows the view has been scrolled up from the most
+        // recent row.
+        let offset = {
+            let mut state = state.lock().unwrap();
+            if !state.following {
+                state.scroll_offset += new_rows;
+            }
+            if let Some((match_lines, match_columns)) = matches {
+                state.match_lines = match_lines;
+                state.match_columns = match_columns;
+            }
+            if layout_changed {
+                state.rows = rows;
+            }
+            state.buffer_len = buffer.len();
+            state.height = content_rows;
+            state.max_scroll_offset = if wrap {
+                // Wrapped lines can take up more than one row. Count how many of the oldest
+                // lines fit on the screen.
+                let mut gutter = stamp_width;
+                if show_line_numbers {
+                    gutter += gutter_width(buffer.len());
+                }
+                let mut rows = 0;
+                let fit = lines
+                    .clone()
+                    .rev()
+                    .take_while(|(i, line, count, _)| {
+                        rows += wrapped_rows(
+                            &displayed(repeated(line, *count, markers.get(i).copied()), whitespace),
+                            (width as usize).saturating_sub(gutter),
+                        );
+                        rows <= content_rows
+                    })
+                    .count();
+                total.saturating_sub(cmp::max(fit, 1))
+            } else {
+                total.saturating_sub(content_rows)
+            };
+            state.scroll_offset = cmp::min(state.scroll_offset, state.max_scroll_offset);
+            state.scroll_offset
+        };
+
+        // The screen is first rendered into a frame with a line for every row. Only rows that
+        // differ from the previous frame are written to the output, that avoids flickering.
+        let mut frame = vec![String::new(); height as usize];
+        let mut row = content_rows;
+        let mut lines = lines.skip(offset).peekable();
+
+        // The gutter with line numbers is as wide as the largest line number on the screen,
+        // which is the number of the most recent line that is shown.
+        self.bottom_line = lines.peek().map(|(i, _, _, _)| i + 1);
+
+        let number_width = match lines.peek() {
+            Some((i, _, _, _)) if show_line_numbers => gutter_width(i + 1),
+            _ => 0,
+        };
+        let gutter = stamp_width + number_width;
+
+        // Lines that are cut off can be panned horizontally, as far as the longest line on the
+        // screen allows. Wrapped lines always fit, so they aren't panned.
+        let h_offset = {
+            let mut state = state.lock().unwrap();
+            state.max_h_offset = if wrap {
+                0
+            } else {
+                lines
+                    .clone()
+                    .take(content_rows)
+                    .map(|(i, line, count, _)| {
+                        displayed(repeated(line, count, markers.get(&i).copied()), whitespace)
+                            .width()
+                    })
+                    .max()
+                    .unwrap_or(0)
+                    .saturating_sub((width as usize).saturating_sub(gutter))
+            };
+            state.h_offset = cmp::min(state.h_offset, state.max_h_offset);
+            state.h_offset
+        };
+        let cursor_line = state.lock().unwrap().cursor_line;
+        let highlight = state.lock().unwrap().highlight;
+        let highlight_levels = state.lock().unwrap().highlight_levels;
+        let anchor = state.lock().unwrap().anchor;
+        let current_match = state.lock().unwrap().current_match;
+        let match_column = state.lock().unwrap().match_column;
+
+        for (i, line, count, matches) in lines {
+            if row == 0 {
+                break;
+            }
+            if !too_slow && Instant::now() > deadline {
+                too_slow = true;
+            }
+            if count == 0 {
+                row -= 1;
+                frame[row] = format!(
+                    "{}{}{}",
+                    color::Fg(color::LightBlack),
+                    line,
+                    color::Fg(color::Reset)
+                );
+                continue;
+            }
+
+            // In replace mode, lines are shown with the replacement applied and only the
+            // replacements are highlighted.
+            if let Some((ref re, ref replacement)) = replace {
+                let (substituted, spans) = substitute(re, replacement, line);
+                let spans: Vec<Span> = spans
+                    .into_iter()
+                    .map(|span| (span, self.theme.match_start()))
+                    .collect();
+                let line = repeated(&substituted, count, markers.get(&i).copied());
+                let (line, spans) = pan(&line, &spans, h_offset);
+                let rendered =
+                    render_line(line, &spans, (width as usize).saturating_sub(gutter), wrap);
+                for rendered in rendered.into_iter().rev().take(row) {
+                    row -= 1;
+                    frame[row] = rendered;
+                }
+                continue;
+            }
+
+            let mut spans: Vec<Span> = match regex {
+                // Outside of filter mode, it hasn't been checked yet whether the line matches.
+                Some(re)
+                    if matches
+                        && highlight
+                        && !too_slow
+                        && self.originals.is_empty()
+                        && (filter || re.is_match(line)) =>
+                {
+                    re.spans(line)
+                        .into_iter()
+                        .map(|span| (span, self.theme.match_start()))
+                        .collect()
+                }
+                _ => Vec::new(),
+            };
+
+            // On the line that was jumped to last, the match that was panned to stands out. When
+            // there's no such match, it's the first match on the line.
+            if current_match == Some(i) {
+                let current = match_column
+                    .and_then(|column| {
+                        spans
+                            .iter()
+                            .position(|(span, _)| line[..span.start].width() == column)
+                    })
+                    .unwrap_or(0);
+                if let Some((_, start)) = spans.get_mut(current) {
+                    *start = self.theme.current_match_start();
+                }
+            }
+
+            // Log levels are at the bottom, then the pinned queries, in the order they were
+            // pinned, and the query is on top.
+            let mut below = if highlight_levels && !too_slow {
+                level_spans(&self.levels, line)
+            } else {
+                Vec::new()
+            };
+            for (index, (_, matcher)) in self.pinned.iter().enumerate() {
+                if too_slow || !matcher.is_match(line) {
+                    continue;
+                }
+
+                let start = theme::pin_start(index);
+                let pinned = matcher
+                    .spans(line)
+                    .into_iter()
+                    .map(|span| (span, start.clone()))
+                    .collect();
+                below = layer(below, pinned);
+            }
+            spans = layer(below, spans);
+            let mut line = repeated(line, count, markers.get(&i).copied());
+            if whitespace {
+                let (shown, moved) = show_whitespace(&line, &spans);
+                line = Cow::Owned(shown);
+                spans = moved;
+            }
+            let (line, spans) = pan(&line, &spans, h_offset);
+
+            let mut rendered =
+                render_line(line, &spans, (width as usize).saturating_sub(gutter), wrap);
+            let is_cursor_line = cursor_line == Some(i);
+            if gutter > 0 {
+                for (j, r) in rendered.iter_mut().enumerate() {
+                    let mut prefix = String::new();
+                    if stamp_width > 0 {
+                        let stamp = match self.arrivals[i] {
+                            Some(arrival) if j == 0 => {
+                                timestamp(timestamps, arrival, self.started).unwrap_or_default()
+                            }
+                            _ => String::new(),
+                        };
+                        prefix.push_str(&format!("{:>w$} ", stamp, w = stamp_width - 1));
+                    }
+                    if number_width > 0 {
+                        let number = if j == 0 {
+                            (i + 1).to_string()
+                        } else {
+                            String::new()
+                        };
+                        let mut number = format!("{:>w$}", number, w = number_width - 1);
+
+                        // `NoFaint` sets the intensity back to normal, which also undoes bold.
+                        if j == 0 && is_cursor_line {
+                            number = format!("{}{}{}", style::Bold, number, style::NoFaint);
+                        }
+                        prefix.push_str(&format!("{} ", number));
+                    }
+                    r.insert_str(
+                        0,
+                        &format!(
+                            "{}{}{}",
+                            color::Fg(color::LightBlack),
+                            prefix,
+                            color::Fg(color::Reset)
+                        ),
+                    );
+                }
+            }
+
+            // The background of the cursor line spans the entire width of the screen. Resetting
+            // the colors of a match would reset the background as well, so it's set again.
+            if is_cursor_line {
+                let background = self.theme.cursor_line_start();
+                let reset = theme::reset();
+                for r in rendered.iter_mut() {
+                    *r = format!(
+                        "{}{}{}{}",
+                        background,
+                        r.replace(&reset, &format!("{}{}", reset, background)),
+                        clear::UntilNewline,
+                        color::Bg(color::Reset)
+                    );
+                }
+            }
+
+            // When a wrapped line doesn't fit entirely, only its last rows are shown.
+            for rendered in rendered.into_iter().rev().take(row) {
+                row -= 1;
+                frame[row] = rendered;
+            }
+        }
+
+        self.too_slow = too_slow;
+
+        // When the lines don't fill the screen, the rows above them are still empty. Anchored to
+        // the top, the lines move up to the first row instead.
+        if anchor == Anchor::Top {
+            frame[..content_rows].rotate_left(row);
+        }
+
+        // While searching, the groups captured in the first line that matches can be shown.
+        let captures = {
+            let state = state.lock().unwrap();
+            match regex {
+                Some(re) if state.show_captures && state.mode == state::Mode::Search => Some(
+                    searched
+                        .iter()
+                        .find(|line| re.is_match(line))
+                        .map(|line| re.captures(line)),
+                ),
+                _ => None,
+            }
+        };
+        let stats = match state.lock().unwrap().mode {
+            state::Mode::Stats => Some(stats(&buffer, self.match_count)),
+            _ => None,
+        };
+        drop(buffer);
+
+        if let Some(captures) = captures {
+            draw_captures(&mut frame, captures, width as usize);
+        }
+
+        // The lock has to be released before the key map is locked again.
+        let mode = state.lock().unwrap().mode;
+        if let state::Mode::Help = mode {
+            let mut state = state.lock().unwrap();
+            let help = state.keymap.help();
+            state.help_offset = draw_box(&mut frame, help, state.help_offset, width as usize);
+        }
+        if let Some(stats) = stats {
+            draw_box(&mut frame, stats, 0, width as usize);
+        }
+
+        let (footer, cursor) = self.footer(width as usize);
+        frame[height as usize - 1] =
+            format!("{}{}{}", self.theme.footer_start(), footer, style::Reset);
+
+        let rendered = match self.renderer {
+            Some(ref mut renderer) => renderer.render(&frame, cursor),
+            None => self.output.render(&frame, cursor),
+        };
+        match rendered {
+            // The output can't keep up, e.g. a slow terminal. The frame is skipped, the next one
+            // shows the latest state anyway.
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            rendered => rendered.map_err(AppError::WriteError),
+        }
+    }
+}
+
+// A line that matched, as it's written by `run_json()`.
+#[derive(Serialize)]
+struct JsonMatch<'a> {
+    line_number: usize,
+    line: &'a str,
+    spans: Vec<(usize, usize)>,
+}
+
+// Write a line to the output, followed by a newline, coloring the spans of bytes that matched the
+// query.
+fn write_highlighted<O: io::Write>(
+    output: &mut O,
+    line: &str,
+    spans: &[Range<usize>],
+    theme: &Theme,
+) -> io::Result<()> {
+    let mut end = 0;
+    for span in spans {
+        write!(
+            output,
+            "{}{}{}{}",
+            &line[end..span.start],
+            theme.match_start(),
+            &line[span.clone()],
+            theme::reset()
+        )?;
+        end = span.end;
+    }
+    writeln!(output, "{}", &line[end..])
+}
+
+// Drop the first `columns` columns of the line. The spans are moved along, spans that are dropped
+// entirely are removed. A wide character that is only partially dropped is dropped entirely.
+fn pan<'a>(line: &'a str, spans: &[Span], columns: usize) -> (&'a str, Vec<Span>) {
+    let mut skipped = 0;
+    let mut start = line.len();
+    for (i, c) in line.char_indices() {
+        if skipped >= columns {
+            start = i;
+            break;
+        }
+        skipped += c.width().unwrap_or(0);
+    }
+
+    let spans = spans
+        .iter()
+        .filter(|(span, _)| span.end > start)
+        .map(|(span, style)| {
+            (
+                span.start.saturating_sub(start)..span.end - start,
+                style.clone(),
+            )
+        })
+        .collect();
+    (&line[start..], spans)
+}
+
+// Return the spans of the log levels in the line, like `ERROR` and `INFO`, each colored by the
+// severity of the level.
+fn level_spans(levels: &Regex, line: &str) -> Vec<Span> {
+    levels
+        .find_iter(line)
+        .map(|m| {
+            let color = match m.as_str() {
+                "ERROR" => color::Fg(color::Red).to_string(),
+                "WARN" | "WARNING" => color::Fg(color::Yellow).to_string(),
+                "INFO" => color::Fg(color::Green).to_string(),
+                _ => color::Fg(color::Blue).to_string(),
+            };
+            (m.start()..m.end(), color)
+        })
+        .collect()
+}
+
+// Combine two sets of spans. Where spans overlap, the spans of `top` win and the spans of
+// `bottom` are cut short.
+fn layer(bottom: Vec<Span>, top: Vec<Span>) -> Vec<Span> {
+    let mut spans = top.clone();
+    for (span, style) in bottom {
+        let mut start = span.start;
+        for (above, _) in &top {
+            if above.end <= start || above.start >= span.end {
+                continue;
+            }
+            if above.start > start {
+                spans.push((start..above.start, style.clone()));
+            }
+            start = cmp::max(start, above.end);
+        }
+        if start < span.end {
+            spans.push((start..span.end, style));
+        }
+    }
+
+    spans.sort_by_key(|(span, _)| span.start);
+    spans
+}
+
+// Return whether a line is shown, given whether it matches the query. In filter mode only the
+// lines that match are shown, when the search is inverted only the lines that don't.
+fn shown(matches: bool, filter: bool, invert: bool) -> bool {
+    (!filter || matches) && (!invert || !matches)
+}
+
+// Return the line followed by a repeat counter like `(x5)`, if it's repeated. When the line ends a
+// block that is repeated, a counter like `(block x3)` is added as well.
+fn repeated(line: &str, count: usize, block: Option<usize>) -> Cow<'_, str> {
+    let mut line = Cow::Borrowed(line);
+    if count > 1 {
+        line = Cow::Owned(format!("{} (x{})", line, count));
+    }
+    if let Some(block) = block {
+        line = Cow::Owned(format!("{} (block x{})", line, block));
+    }
+
+    line
+}
+
+// Replace whitespace and control characters by glyphs that can be seen, e.g. `·` for a space and
+// `␍` for a carriage return. The glyphs are dimmed, unless they're part of a span. The spans are
+// moved along with the text they cover. Every glyph is a single column wide, like the space it
+// replaces. Tabs are expanded when lines are read, so they show up as spaces.
+fn show_whitespace(line: &str, spans: &[Span]) -> (String, Vec<Span>) {
+    let mut shown = String::with_capacity(line.len());
+    let mut glyphs = Vec::new();
+
+    // Maps every byte offset in the line to the byte offset in the shown line.
+    let mut offsets = vec![0; line.len() + 1];
+    for (i, c) in line.char_indices() {
+        offsets[i] = shown.len();
+        match glyph(c) {
+            Some(glyph) => {
+                let start = shown.len();
+                shown.push(glyph);
+                glyphs.push((start..shown.len(), color::Fg(color::LightBlack).to_string()));
+            }
+            None => shown.push(c),
+        }
+    }
+    offsets[line.len()] = shown.len();
+
+    let spans = spans
+        .iter()
+        .map(|(span, style)| (offsets[span.start]..offsets[span.end], style.clone()))
+        .collect();
+
+    (shown, layer(glyphs, spans))
+}
+
+// Return the glyph that is shown instead of a whitespace or control character.
+fn glyph(c: char) -> Option<char> {
+    match c {
+        ' ' => Some('·'),
+        '\t' => Some('→'),
+
+        // Unicode has a block of pictures for the control characters, in the same order.
+        '\x00'..='\x1f' => char::from_u32(0x2400 + c as u32),
+        '\x7f' => Some('␡'),
+        _ => None,
+    }
+}
+
+// Return the line as it's drawn, with whitespace replaced by glyphs when `whitespace` is set.
+fn displayed(line: Cow<'_, str>, whitespace: bool) -> Cow<'_, str> {
+    if whitespace {
+        Cow::Owned(show_whitespace(&line, &[]).0)
+    } else {
+        line
+    }
+}
+
+// Replace every match of the regex in the line by the replacement, like `Regex::replace_all()`
+// does. The spans of the replacements in the returned line are returned as well.
+fn substitute(regex: &Regex, replacement: &str, line: &str) -> (String, Vec<Range<usize>>) {
+    let mut substituted = String::with_capacity(line.len());
+    let mut spans = Vec::new();
+    let mut end = 0;
+
+    for captures in regex.captures_iter(line) {
+        let m = captures.get(0).unwrap();
+        substituted.push_str(&line[end..m.start()]);
+        let start = substituted.len();
+        captures.expand(replacement, &mut substituted);
+        spans.push(start..substituted.len());
+        end = m.end();
+    }
+    substituted.push_str(&line[end..]);
+
+    (substituted, spans)
+}
+
+// Find blocks of two or more lines that are repeated right after each other. Every repeated block
+// is returned as the range of all lines of the repetitions, together with the size of the block.
+fn find_blocks(lines: &VecDeque<String>) -> Vec<Block> {
+    let same = |a: usize, b: usize, size: usize| (0..size).all(|j| lines[a + j] == lines[b + j]);
+
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let size = (2..=MAX_BLOCK_LINES)
+            .take_while(|size| i + 2 * size <= lines.len())
+            .find(|&size| same(i, i + size, size));
+
+        match size {
+            Some(size) => {
+                let mut end = i + 2 * size;
+                while end + size <= lines.len() && same(i, end, size) {
+                    end += size;
+                }
+                blocks.push((i..end, size));
+                i = end;
+            }
+            None => i += 1,
+        }
+    }
+
+    blocks
+}
+
+// Render a line, coloring the spans of bytes that matched the query. Every item of the returned
+// vector is a row on the screen. Lines that are wider than `width` columns are either wrapped
+// over multiple rows, or cut off with an ellipsis.
+fn render_line(line: &str, spans: &[Span], width: usize, wrap: bool) -> Vec<String> {
+    let truncate = !wrap && line.width() > width;
+    let budget = if truncate {
+        width.saturating_sub(1)
+    } else {
+        width
+    };
+
+    let mut rows = vec![String::new()];
+    let mut columns = 0;
+    let mut highlighted = false;
+    let mut spans = spans.iter().filter(|(span, _)| !span.is_empty()).peekable();
+
+    for (i, c) in line.char_indices() {
+        let w = c.width().unwrap_or(0);
+        if columns + w > budget {
+            if !wrap || columns == 0 {
+                break;
+            }
+
+            // Highlighting must not leak into the footer, so it's stopped at the end of every
+            // row and continued on the next one.
+            if highlighted {
+                rows.last_mut().unwrap().push_str(&theme::reset());
+            }
+            rows.push(String::new());
+            if let (true, Some((_, start))) = (highlighted, spans.peek()) {
+                rows.last_mut().unwrap().push_str(start);
+            }
+            columns = 0;
+        }
+
+        let row = rows.last_mut().unwrap();
+        if let Some((span, _)) = spans.peek() {
+            if highlighted && i >= span.end {
+                row.push_str(&theme::reset());
+                highlighted = false;
+                spans.next();
+            }
+        }
+
+        if let Some((span, start)) = spans.peek() {
+            if !highlighted && i >= span.start {
+                row.push_str(start);
+                highlighted = true;
+            }
+        }
+
+        row.push(c);
+        columns += w;
+    }
+
+    let row = rows.last_mut().unwrap();
+    if highlighted {
+        row.push_str(&theme::reset());
+    }
+
+    if truncate && width > 0 {
+        row.push('…');
+    }
+
+    rows
+}
+
+// Return the lines of the statistics screen: the number of lines and bytes in the buffer, the
+// width of the longest line and, if there's a query, how many lines match it. The number of
+// bytes includes a line break for every line.
+fn stats(buffer: &VecDeque<String>, match_count: Option<usize>) -> Vec<String> {
+    let (mut bytes, mut longest) = (0, 0);
+    for line in buffer {
+        bytes += line.len() + 1;
+        longest = cmp::max(longest, line.width());
+    }
+
+    let mut stats = vec![
+        format!("Lines:         {}", buffer.len()),
+        format!("Bytes:         {}", bytes),
+        format!("Longest line:  {} columns", longest),
+    ];
+    if let Some(matches) = match_count {
+        let percentage = if buffer.is_empty() {
+            0.0
+        } else {
+            matches as f64 * 100.0 / buffer.len() as f64
+        };
+        stats.push(format!("Matches:       {} ({:.1}%)", matches, percentage));
+    }
+
+    stats
+}
+
+// Draw lines as a box in the middle of the frame, like the help screen. When the lines don't fit,
+// the first `offset` lines are skipped and the last row of the box tells how to scroll. Returns
+// the offset clamped to the lines that can be scrolled.
+fn draw_box(frame: &mut [String], mut lines: Vec<String>, offset: usize, width: usize) -> usize {
+    let mut box_width = cmp::min(
+        lines.iter().map(|line| line.width()).max().unwrap_or(0) + 4,
+        width,
+    );
+
+    // The last row is reserved for the footer, and the box has an empty row above and below
+    // the lines.
+    let rows = frame.len().saturating_sub(1);
+    let space = rows.saturating_sub(2);
+    let mut offset = offset;
+    if lines.len() > space {
+        // One row goes to the hint about scrolling.
+        let shown = space.saturating_sub(1);
+        offset = cmp::min(offset, lines.len() - shown);
+        let hint = format!(
+            "-- lines {}-{} of {}, j/k to scroll --",
+            offset + 1,
+            offset + shown,
+            lines.len()
+        );
+        box_width = cmp::min(cmp::max(box_width, hint.width() + 4), width);
+        lines = lines.into_iter().skip(offset).take(shown).collect();
+        lines.push(hint);
+    } else {
+        offset = 0;
+    }
+
+    let top = rows.saturating_sub(lines.len() + 2) / 2;
+    let left = (width - box_width) / 2;
+
+    let lines = iter::once(String::new())
+        .chain(lines)
+        .chain(iter::once(String::new()));
+    for (row, line) in frame.iter_mut().skip(top).take(rows - top).zip(lines) {
+        let line = format!("  {:<w$}", line, w = box_width.saturating_sub(2));
+        *row = format!(
+            "{}{}{}{}",
+            " ".repeat(left),
+            style::Invert,
+            render_line(&line, &[], box_width, false)[0],
+            style::Reset
+        );
+    }
+
+    offset
+}
+
+// Draw the groups captured by the query on the rows right above the footer, one group per row.
+// `captures` is `None` when no line matches.
+fn draw_captures(
+    frame: &mut [String],
+    captures: Option<Vec<(String, Option<String>)>>,
+    width: usize,
+) {
+    let lines: Vec<String> = match captures {
+        None => vec!["No match".to_string()],
+        Some(ref groups) if groups.is_empty() => vec!["No capture groups".to_string()],
+        Some(groups) => groups
+            .into_iter()
+            .map(|(label, text)| match text {
+                Some(text) => format!("{}: {}", label, text),
+                None => format!("{}: (no match)", label),
+            })
+            .collect(),
+    };
+
+    // The last row is reserved for the footer. When there are more groups than rows, the first
+    // groups are shown.
+    let rows = frame.len().saturating_sub(1);
+    let top = rows.saturating_sub(lines.len());
+    for (row, line) in frame[top..rows].iter_mut().zip(lines) {
+        let line = format!(" {:<w$}", line, w = width.saturating_sub(1));
+        *row = format!(
+            "{}{}{}",
+            style::Invert,
+            render_line(&line, &[], width, false)[0],
+            style::Reset
+        );
+    }
+}
+
+// Return what the gutter shows of the time a line was read, if anything. The time of the day is
+// the local time.
+fn timestamp(timestamps: Timestamps, arrival: SystemTime, started: SystemTime) -> Option<String> {
+    match timestamps {
+        Timestamps::Off => None,
+        Timestamps::Clock => {
+            let since_epoch = arrival.duration_since(UNIX_EPOCH).unwrap_or_default();
+            let seconds = since_epoch.as_secs() as libc::time_t;
+
+            // `localtime_r()` only writes to the given struct, which may be all zeroes.
+            let mut time: libc::tm = unsafe { mem::zeroed() };
+            if unsafe { libc::localtime_r(&seconds, &mut time) }.is_null() {
+                return None;
+            }
+            Some(format!(
+                "{:02}:{:02}:{:02}",
+                time.tm_hour, time.tm_min, time.tm_sec
+            ))
+        }
+        Timestamps::Elapsed => {
+            let elapsed = arrival.duration_since(started).unwrap_or_default();
+            Some(format!("+{:.1}s", elapsed.as_secs_f64()))
+        }
+    }
+}
+
+// Return the width of the gutter that fits the given line number, including a space to separate
+// it from the line.
+fn gutter_width(line_number: usize) -> usize {
+    line_number.to_string().len() + 1
+}
+
+// Return the number of rows `render_line()` needs for a wrapped line.
+fn wrapped_rows(line: &str, width: usize) -> usize {
+    let mut rows = 1;
+    let mut columns = 0;
+    for c in line.chars() {
+        let w = c.width().unwrap_or(0);
+        if columns + w > width {
+            if columns == 0 {
+                break;
+            }
+            rows += 1;
+            columns = 0;
+        }
+        columns += w;
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{Recorder, Style};
+    use std::env;
+    use std::fs;
+    use std::process;
+    use termion::cursor;
+    use termion::screen;
+
+    // Return an app with the lines in its buffer, that draws on a screen of 40 by 10.
+    fn app(lines: &[&str]) -> App<Vec<u8>> {
+        let mut app = App::new(Vec::new()).size(40, 10);
+        for line in lines {
+            app.push_line(line, None);
+        }
+        app
+    }
+
+    // Like `app()`, but the frames are recorded by the returned recorder.
+    fn recorded(lines: &[&str]) -> (App<Vec<u8>>, Recorder) {
+        let recorder = Recorder::new();
+        let app = app(lines).renderer(recorder.clone());
+        (app, recorder)
+    }
+
+    // Return the text on every row of the last frame.
+    fn rows(recorder: &Recorder) -> Vec<String> {
+        recorder
+            .last_frame()
+            .unwrap()
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.c).collect())
+            .collect()
+    }
+
+    // Return the rows of the last frame that show lines, skipping the empty rows and the footer.
+    fn shown_lines(recorder: &Recorder) -> Vec<String> {
+        let mut rows = rows(recorder);
+        rows.pop();
+        rows.retain(|row| !row.is_empty());
+        rows
+    }
+
+    // Return the characters on the row of the last frame that are drawn in another style than
+    // the default one, like the matches.
+    fn highlighted(recorder: &Recorder, row: usize) -> String {
+        recorder.last_frame().unwrap()[row]
+            .iter()
+            .filter(|cell| cell.style != Style::default())
+            .map(|cell| cell.c)
+            .collect()
+    }
+
+    // Like `highlighted()`, but every run of highlighted characters separately.
+    fn highlights(recorder: &Recorder, row: usize) -> Vec<String> {
+        let mut highlights: Vec<String> = Vec::new();
+        let mut previous = Style::default();
+        for cell in &recorder.last_frame().unwrap()[row] {
+            if cell.style != Style::default() {
+                match highlights.last_mut() {
+                    Some(highlight) if cell.style == previous => highlight.push(cell.c),
+                    _ => highlights.push(cell.c.to_string()),
+                }
+            }
+            previous = cell.style;
+        }
+        highlights
+    }
+
+    // Type the keys, like the user would.
+    fn press<W: io::Write>(app: &mut App<W>, keys: &[Key]) {
+        for &key in keys {
+            app.state.lock().unwrap().process_key(key).unwrap();
+        }
+    }
+
+    fn type_text<W: io::Write>(app: &mut App<W>, text: &str) {
+        let keys: Vec<Key> = text.chars().map(Key::Char).collect();
+        press(app, &keys);
+    }
+
+    #[test]
+    fn footer_fits_narrow_screens() {
+        let mut app = app(&["a line"]);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "a query that is longer than the screen");
+
+        for width in 0..60 {
+            let (footer, _) = app.footer(width);
+            assert_eq!(footer.width(), width);
+        }
+    }
+
+    #[test]
+    fn incomplete_regex_is_drawn() {
+        let (mut app, recorder) = recorded(&["(a line)"]);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "a");
+        app.redraw().unwrap();
+        type_text(&mut app, "(");
+
+        assert!(app.redraw().is_ok());
+        assert!(app.invalid_regex);
+        assert_eq!(rows(&recorder)[8], "(a line)");
+
+        // The footer shows a red `!`, while the matches of the last valid regex stay highlighted.
+        let frame = recorder.last_frame().unwrap();
+        let footer = frame.last().unwrap();
+        assert!(footer
+            .iter()
+            .any(|cell| cell.c == '!' && cell.style.fg == Some(1)));
+        assert_eq!(highlighted(&recorder, 8), "a");
+    }
+
+    #[test]
+    fn buffer_larger_than_the_screen_is_drawn() {
+        let mut lines = vec!["the first line".to_string()];
+        lines.extend((0..100).map(|i| format!("line {}", i)));
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let (mut app, recorder) = recorded(&lines);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "first");
+
+        assert!(app.redraw().is_ok());
+        assert_eq!(rows(&recorder)[8], "line 99");
+    }
+
+    #[test]
+    fn case_insensitive_search() {
+        let (mut app, recorder) = recorded(&["an error"]);
+        press(&mut app, &[Key::Char('/'), Key::Char('\t')]);
+        type_text(&mut app, "ERROR");
+        app.redraw().unwrap();
+
+        assert_eq!(highlighted(&recorder, 8), "error");
+    }
+
+    #[test]
+    fn every_match_is_highlighted() {
+        let (mut app, recorder) = recorded(&["foo bar foo bar foo"]);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "foo");
+        app.redraw().unwrap();
+
+        assert_eq!(highlights(&recorder, 8), vec!["foo", "foo", "foo"]);
+    }
+
+    #[test]
+    fn filter_hides_lines_that_dont_match() {
+        let lines = [
+            "one",
+            "two",
+            "match three",
+            "four",
+            "match five",
+            "six",
+            "seven",
+            "eight",
+            "match nine",
+            "ten",
+        ];
+        let (mut app, recorder) = recorded(&lines);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "match");
+        press(&mut app, &[Key::Ctrl('o')]);
+        app.redraw().unwrap();
+
+        assert_eq!(
+            shown_lines(&recorder),
+            vec!["match three", "match five", "match nine"]
+        );
+    }
+
+    #[test]
+    fn invert_hides_lines_that_match() {
+        let (mut app, recorder) = recorded(&["keep one", "drop two", "keep three", "drop four"]);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "keep");
+        press(&mut app, &[Key::Ctrl('v')]);
+        app.redraw().unwrap();
+
+        assert_eq!(shown_lines(&recorder), vec!["drop two", "drop four"]);
+    }
+
+    #[test]
+    fn footer_counts_the_matching_lines() {
+        let lines: Vec<String> = (0..20)
+            .map(|i| match i % 4 {
+                0 => format!("hit {}", i),
+                _ => format!("miss {}", i),
+            })
+            .collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut app = app(&lines);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "hit");
+        app.redraw().unwrap();
+
+        let (footer, _) = app.footer(80);
+        assert!(footer.contains("5 matches"), "{}", footer);
+    }
+
+    #[test]
+    fn buffer_keeps_the_most_recent_lines() {
+        let mut app = App::new(Vec::new()).max_lines(100);
+        for i in 0..1000 {
+            app.push_line(&format!("line {}", i), None);
+        }
+
+        let lines = app.lines();
+        assert_eq!(lines.len(), 100);
+        assert_eq!(lines[0], "line 900");
+        assert_eq!(lines[99], "line 999");
+    }
+
+    #[test]
+    fn regex_is_only_compiled_when_the_query_changes() {
+        let (mut app, recorder) = recorded(&["foo bar"]);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "foo");
+        app.redraw().unwrap();
+
+        // Had the regex been compiled again, `foo` would be highlighted instead of `bar`.
+        app.regex = Some(Matcher::new(&["bar".to_string()]).unwrap());
+        app.push_line("foo bar", None);
+        app.redraw().unwrap();
+
+        assert_eq!(highlighted(&recorder, 8), "bar");
+        assert_eq!(highlighted(&recorder, 7), "bar");
+    }
+
+    #[test]
+    fn long_lines_are_cut_off() {
+        let rows = render_line(&"x".repeat(100), &[], 40, false);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].width(), 40);
+        assert!(rows[0].ends_with('…'));
+
+        // A wide character that doesn't fit is left out as a whole.
+        let rows = render_line(&"全".repeat(30), &[], 40, false);
+        assert_eq!(rows[0], format!("{}…", "全".repeat(19)));
+    }
+
+    #[test]
+    fn wrapped_lines_take_up_more_rows() {
+        let rows = render_line(&"x".repeat(80), &[], 40, true);
+        assert_eq!(rows, vec!["x".repeat(40), "x".repeat(40)]);
+
+        let (mut app, recorder) = recorded(&[&"y".repeat(80)]);
+        press(&mut app, &[Key::Char('w')]);
+        app.redraw().unwrap();
+        assert_eq!(shown_lines(&recorder), vec!["y".repeat(40), "y".repeat(40)]);
+    }
+
+    #[test]
+    fn gutter_grows_with_the_line_numbers() {
+        let lines: Vec<String> = (1..=10).map(|i| format!("line {}", i)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let (mut app, recorder) = recorded(&lines[..9]);
+        press(&mut app, &[Key::Char('#')]);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], "9 line 9");
+
+        app.push_line(lines[9], None);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], "10 line 10");
+        assert_eq!(rows(&recorder)[7], " 9 line 9");
+    }
+
+    #[test]
+    fn colored_input_is_matched_as_plain_text() {
+        let (mut app, recorder) = recorded(&["a \x1b[31mred\x1b[0m word"]);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "red word");
+        app.redraw().unwrap();
+
+        assert_eq!(*app.lines(), vec!["a red word"]);
+        assert_eq!(highlighted(&recorder, 8), "red word");
+    }
+
+    #[test]
+    fn view_follows_new_lines_until_scrolled_up() {
+        let lines: Vec<String> = (0..20).map(|i| format!("line {}", i)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let (mut app, recorder) = recorded(&lines);
+        app.redraw().unwrap();
+        app.push_line("line 20", None);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], "line 20");
+
+        // Scrolled up, the view stays where it is.
+        press(&mut app, &[Key::Up]);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], "line 19");
+        app.push_line("line 21", None);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], "line 19");
+
+        // Jumping to the end follows the input again.
+        press(&mut app, &[Key::End]);
+        app.push_line("line 22", None);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], "line 22");
+    }
+
+    #[test]
+    fn lines_are_read_from_the_input() {
+        let mut app = App::new(Vec::new()).input(&b"first\nsecond\r\nthird"[..]);
+        app.read_all().unwrap();
+
+        assert_eq!(*app.lines(), vec!["first", "second", "third"]);
+    }
+
+    // Lines that are sent over a channel, and end when the channel is closed.
+    struct ChannelSource(Receiver<String>);
+
+    impl LineSource for ChannelSource {
+        fn next_line(&mut self) -> Result<Option<String>> {
+            Ok(self.0.recv().ok())
+        }
+    }
+
+    #[test]
+    fn keys_are_handled_while_lines_come_in() {
+        let (lines, source) = channel::unbounded();
+        let (keys, events) = channel::unbounded();
+        let mut app = app(&[]).source(ChannelSource(source));
+
+        let sender = thread::spawn(move || {
+            let pause = || thread::sleep(Duration::from_millis(50));
+            lines.send("first".to_string()).unwrap();
+            pause();
+            keys.send(Event::Key(Key::Char('/'))).unwrap();
+            pause();
+            lines.send("second".to_string()).unwrap();
+            pause();
+            keys.send(Event::Key(Key::Char('s'))).unwrap();
+            drop(lines);
+            pause();
+            keys.send(Event::Key(Key::Ctrl('c'))).unwrap();
+        });
+        app.start(events).unwrap();
+        sender.join().unwrap();
+
+        assert_eq!(*app.lines(), vec!["first", "second"]);
+        assert_eq!(app.current_query(), "s");
+    }
+
+    // Returns the lines, and then fails.
+    struct FailingSource(Vec<String>);
+
+    impl LineSource for FailingSource {
+        fn next_line(&mut self) -> Result<Option<String>> {
+            match self.0.pop() {
+                Some(line) => Ok(Some(line)),
+                None => Err(AppError::ReadError(io::Error::other("gone"))),
+            }
+        }
+    }
+
+    #[test]
+    fn lines_are_read_from_the_source() {
+        let lines = vec!["first".to_string(), "second".to_string()];
+        let mut app = App::new(Vec::new()).source(lines.into_iter());
+        app.read_all().unwrap();
+        assert_eq!(*app.lines(), vec!["first", "second"]);
+
+        let mut app = App::new(Vec::new()).source(FailingSource(vec!["only".to_string()]));
+        assert!(matches!(app.read_all(), Err(AppError::ReadError(_))));
+        assert_eq!(*app.lines(), vec!["only"]);
+    }
+
+    #[test]
+    fn help_shows_the_bindings() {
+        let recorder = Recorder::new();
+        let mut app = App::new(Vec::new()).size(80, 12).renderer(recorder.clone());
+        press(&mut app, &[Key::Char('?')]);
+        app.redraw().unwrap();
+        let help = rows(&recorder).join("\n");
+        assert!(help.contains("Normal mode"), "{}", help);
+        assert!(help.contains("Scroll up one line"), "{}", help);
+        assert!(help.contains("-- lines 1-8 of"), "{}", help);
+
+        // The help doesn't fit, j scrolls it.
+        press(&mut app, &[Key::Char('j')]);
+        app.redraw().unwrap();
+        let help = rows(&recorder).join("\n");
+        assert!(!help.contains("Normal mode"), "{}", help);
+        assert!(help.contains("-- lines 2-9 of"), "{}", help);
+
+        // Any other key closes it.
+        press(&mut app, &[Key::Char('x')]);
+        assert_eq!(app.mode(), state::Mode::Normal);
+    }
+
+    #[test]
+    fn matches_are_drawn_in_the_colors_of_the_theme() {
+        let theme = Theme {
+            match_fg: Some(AnsiValue(208)),
+            match_bg: Some(AnsiValue(17)),
+            ..Theme::default()
+        };
+        let (app, recorder) = recorded(&["an error"]);
+        let mut app = app.theme(theme);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "error");
+        app.redraw().unwrap();
+
+        let cell = recorder.last_frame().unwrap()[8][3];
+        assert_eq!(cell.c, 'e');
+        assert_eq!(cell.style.fg, Some(208));
+        assert_eq!(cell.style.bg, Some(17));
+    }
+
+    // Start the app with the keys already pressed, the last one quits it.
+    fn start<W: io::Write>(app: &mut App<W>, keys: &[Key]) -> Result<()> {
+        let (sender, events) = channel::unbounded();
+        for &key in keys.iter().chain(&[Key::Ctrl('c')]) {
+            sender.send(Event::Key(key)).unwrap();
+        }
+        app.start(events)
+    }
+
+    #[test]
+    fn history_is_loaded_and_saved() {
+        let dir = env::temp_dir().join(format!("kurzhozen-history-{}", process::id()));
+        let path = dir.join("history");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "old query\n").unwrap();
+
+        let mut app = App::new(Vec::new()).input(io::empty()).history_file(&path);
+        start(&mut app, &[Key::Char('/'), Key::Up]).unwrap();
+        assert_eq!(app.current_query(), "old query");
+
+        let mut app = App::new(Vec::new()).input(io::empty()).history_file(&path);
+        let keys: Vec<Key> = "/new\n".chars().map(Key::Char).collect();
+        start(&mut app, &keys).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old query\nnew\n");
+
+        // Without a file to append to, the history is lost, but that's only a warning.
+        let mut app = App::new(Vec::new()).input(io::empty()).history_file(&dir);
+        start(&mut app, &keys).unwrap();
+        assert_eq!(app.warnings().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn app_is_built_with_the_options() {
+        let recorder = Recorder::new();
+        let mut app = App::new(Vec::new())
+            .input(&b"one\ntwo\nthree\n"[..])
+            .max_lines(2)
+            .line_numbers(true)
+            .wrap(true)
+            .follow(false)
+            .size(40, 5)
+            .renderer(recorder.clone());
+        app.read_all().unwrap();
+        app.redraw().unwrap();
+
+        assert_eq!(*app.lines(), ["two", "three"]);
+        assert_eq!(rows(&recorder)[3], "2 three");
+        let state = app.state.lock().unwrap();
+        assert!(state.wrap);
+        assert!(!state.following);
+    }
+
+    #[test]
+    fn identical_lines_are_shown_once() {
+        let (mut app, recorder) =
+            recorded(&["first", "same", "same", "same", "same", "same", "last"]);
+        press(&mut app, &[Key::Char('d')]);
+        app.redraw().unwrap();
+        assert_eq!(shown_lines(&recorder), vec!["first", "same (x5)", "last"]);
+
+        // The buffer is left alone, without dedup every line is shown again.
+        press(&mut app, &[Key::Char('d')]);
+        app.redraw().unwrap();
+        assert_eq!(shown_lines(&recorder).len(), 7);
+    }
+
+    // An output that can still be read while the app owns it.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn empty_query_highlights_nothing() {
+        let output = Output::default();
+        let mut app = App::new(output.clone()).size(40, 10);
+        app.push_line("a line", None);
+        app.push_line("another line", None);
+        press(&mut app, &[Key::Char('/')]);
+        app.redraw().unwrap();
+
+        // Only the footer, on the last row, is drawn in color.
+        let text = output.text();
+        let text = &text[..text.find("\x1b[10;1H").unwrap()];
+        assert!(text.contains("another line"), "{:?}", text);
+        assert!(!text.contains("\x1b[38;5;"), "{:?}", text);
+        assert!(!text.contains("\x1b[48;5;"), "{:?}", text);
+        assert_eq!(app.match_count, None);
+    }
+
+    #[test]
+    fn footer_shows_the_position() {
+        let lines: Vec<String> = (1..=200).map(|i| format!("line {}", i)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let (mut app, recorder) = recorded(&lines);
+        app.redraw().unwrap();
+        assert!(app.footer(80).0.contains("200/200 (BOT)"));
+
+        press(&mut app, &[Key::Up; 55]);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], "line 145");
+        assert!(app.footer(80).0.contains("145/200 (72%)"));
+    }
+
+    #[test]
+    fn only_the_shown_lines_are_saved() {
+        let mut app = app(&["an error", "fine", "another error", "also fine"]);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "error");
+        press(&mut app, &[Key::Ctrl('o')]);
+        app.redraw().unwrap();
+
+        let mut saved = Vec::new();
+        assert_eq!(app.write_shown(&mut saved).unwrap(), 2);
+        assert_eq!(saved, b"an error\nanother error\n");
+
+        // Inverted, the other lines are saved.
+        press(&mut app, &[Key::Ctrl('o'), Key::Ctrl('v')]);
+        let mut saved = Vec::new();
+        assert_eq!(app.write_shown(&mut saved).unwrap(), 2);
+        assert_eq!(saved, b"fine\nalso fine\n");
+    }
+
+    #[test]
+    fn footer_fits_a_query_of_wide_characters() {
+        let mut app = app(&["全角"]);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "全角の検索");
+
+        for width in 0..60 {
+            let (footer, cursor) = app.footer(width);
+            assert_eq!(footer.width(), width, "{}", footer);
+            assert!(cursor.is_none_or(|column| column <= width));
+        }
+        assert!(app.footer(80).0.starts_with("/全角の検索 "));
+    }
+
+    #[test]
+    fn captured_groups_are_shown() {
+        let (mut app, recorder) = recorded(&["12-34"]);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, r"(\d+)-(?P<second>\d+)");
+        press(&mut app, &[Key::Ctrl('g')]);
+        app.redraw().unwrap();
+        let groups = rows(&recorder);
+        assert_eq!(groups[7].trim_end(), " 1: 12");
+        assert_eq!(groups[8].trim_end(), " second: 34");
+
+        type_text(&mut app, "x");
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8].trim_end(), " No match");
+    }
+
+    #[test]
+    fn redraws_are_coalesced_while_lines_come_in() {
+        let input: String = (0..10_000).map(|i| format!("line {}\n", i)).collect();
+        let (app, recorder) = recorded(&[]);
+        let mut app = app.input(io::Cursor::new(input));
+
+        let (keys, events) = channel::unbounded();
+        let quitter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            keys.send(Event::Key(Key::Ctrl('c'))).unwrap();
+        });
+        app.start(events).unwrap();
+        quitter.join().unwrap();
+
+        assert_eq!(app.lines().len(), 10_000);
+        assert!(recorder.frames().len() < 100, "{}", recorder.frames().len());
+
+        // Nothing is lost, the last frame shows the last line.
+        assert_eq!(rows(&recorder)[8], "line 9999");
+    }
+
+    #[test]
+    fn redraw_works_without_a_terminal() {
+        let output = Output::default();
+        let mut app = App::new(output.clone());
+        for i in 0..100 {
+            app.push_line(&format!("line {}", i), None);
+        }
+
+        // Without a terminal, the screen has the default size.
+        app.redraw().unwrap();
+        if termion::terminal_size().is_err() {
+            let (width, height) = DEFAULT_SIZE;
+            assert!(output.text().contains(&format!("\x1b[{};1H", height)));
+            assert_eq!(app.footer(width as usize).0.width(), width as usize);
+        }
+        assert!(output.text().contains("line 99"));
+    }
+
+    #[test]
+    fn batch_mode_prints_the_matching_lines() {
+        let output = Output::default();
+        let mut app = App::new(output.clone()).input(&b"an error\nfine\nanother error\n"[..]);
+        app.run_batch("error", SearchOptions::default(), false)
+            .unwrap();
+        assert_eq!(output.text(), "an error\nanother error\n");
+
+        // Highlighted, the matches are colored, but nothing else is written.
+        let output = Output::default();
+        let mut app = App::new(output.clone()).input(&b"an error\nfine\n"[..]);
+        app.run_batch("error", SearchOptions::default(), true)
+            .unwrap();
+        let text = output.text();
+        assert!(text.starts_with("an "), "{:?}", text);
+        assert!(text.contains("error"), "{:?}", text);
+        assert!(!text.contains("fine"), "{:?}", text);
+        assert_ne!(text, "an error\n");
+    }
+
+    #[test]
+    fn initial_query_is_highlighted() {
+        let (app, recorder) = recorded(&["a warning", "fine", "warn again"]);
+        let mut app = app.query("warn");
+        app.redraw().unwrap();
+
+        assert_eq!(app.mode(), state::Mode::Search);
+        assert_eq!(app.current_query(), "warn");
+        assert_eq!(highlighted(&recorder, 6), "warn");
+        assert_eq!(highlighted(&recorder, 7), "");
+        assert_eq!(highlighted(&recorder, 8), "warn");
+    }
+
+    #[test]
+    fn panning_right_shows_the_end_of_long_lines() {
+        let line = format!("{}{}{}", "a".repeat(45), "xyz", "b".repeat(12));
+        let (mut app, recorder) = recorded(&[&line]);
+        press(&mut app, &[Key::Char('/')]);
+        type_text(&mut app, "xyz");
+        press(&mut app, &[Key::Char('\n')]);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], format!("{}…", "a".repeat(39)));
+
+        press(&mut app, &[Key::Right]);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], format!("{}xy…", "a".repeat(37)));
+        assert_eq!(highlighted(&recorder, 8), "xy");
+
+        // Panning stops at the end of the longest line.
+        press(&mut app, &[Key::Right; 5]);
+        app.redraw().unwrap();
+        assert_eq!(app.state.lock().unwrap().h_offset, 20);
+        assert_eq!(
+            rows(&recorder)[8],
+            format!("{}xyz{}", "a".repeat(25), "b".repeat(12))
+        );
+        assert_eq!(highlights(&recorder, 8), vec!["xyz"]);
+    }
+
+    #[test]
+    fn cursor_line_has_a_background() {
+        let lines: Vec<String> = (1..=9).map(|i| format!("line {}", i)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let (mut app, recorder) = recorded(&lines);
+        press(&mut app, &[Key::Char('c'), Key::Down]);
+        app.redraw().unwrap();
+
+        let cursor = app.state.lock().unwrap().cursor_line.unwrap();
+        let frame = recorder.last_frame().unwrap();
+        let background = Theme::default().cursor_line_bg.map(|color| color.0);
+        for (row, cells) in frame[..9].iter().enumerate() {
+            let on_cursor = cells.iter().filter(|cell| cell.style.bg == background);
+            match row == cursor {
+                true => assert_eq!(on_cursor.count(), cells.len(), "row {}", row),
+                false => assert_eq!(on_cursor.count(), 0, "row {}", row),
+            }
+        }
+
+        // The rest of the row is cleared while the background is set, so the background covers
+        // the whole row, not just the line.
+        let output = Output::default();
+        let mut app = App::new(output.clone()).size(40, 10);
+        app.push_line("the only line", None);
+        press(&mut app, &[Key::Char('c')]);
+        app.redraw().unwrap();
+        let row = format!(
+            "{}the only line{}",
+            Theme::default().cursor_line_start(),
+            clear::UntilNewline
+        );
+        assert!(output.text().contains(&row), "{:?}", output.text());
+    }
+
+    #[test]
+    fn highlighting_is_turned_off_without_losing_the_search() {
+        let (mut app, recorder) = recorded(&["an error", "fine", "another error"]);
+        type_text(&mut app, "/error");
+        press(&mut app, &[Key::Char('\n')]);
+        app.redraw().unwrap();
+        assert_eq!(highlighted(&recorder, 8), "error");
+
+        type_text(&mut app, ":noh\n");
+        app.redraw().unwrap();
+        assert_eq!(highlighted(&recorder, 6), "");
+        assert_eq!(highlighted(&recorder, 8), "");
+
+        // The matches can still be jumped to.
+        type_text(&mut app, "n");
+        app.redraw().unwrap();
+        assert!(app.state.lock().unwrap().current_match.is_some());
+        assert_eq!(highlighted(&recorder, 8), "");
+
+        // A new search highlights again.
+        type_text(&mut app, "/");
+        app.redraw().unwrap();
+        assert_eq!(highlighted(&recorder, 8), "error");
+    }
+
+    #[test]
+    fn unset_options_are_left_alone() {
+        let config = Config::parse("max_lines = 2\nline_numbers = true").unwrap();
+        let mut app = App::new(Vec::new()).wrap(true).config(&config);
+        for line in &["one", "two", "three"] {
+            app.push_line(line, None);
+        }
+
+        assert_eq!(*app.lines(), vec!["two", "three"]);
+        let state = app.state.lock().unwrap();
+        assert!(state.show_line_numbers);
+        assert!(state.wrap);
+    }
+
+    #[test]
+    fn log_levels_are_colored() {
+        let theme = Theme {
+            match_fg: Some(AnsiValue(208)),
+            ..Theme::default()
+        };
+        let (app, recorder) = recorded(&["12:00 ERROR failed"]);
+        let mut app = app.theme(theme);
+        app.redraw().unwrap();
+        assert_eq!(highlighted(&recorder, 8), "");
+
+        press(&mut app, &[Key::Char('H')]);
+        app.redraw().unwrap();
+        assert_eq!(highlighted(&recorder, 8), "ERROR");
+        assert_eq!(recorder.last_frame().unwrap()[8][6].style.fg, Some(1));
+
+        // Matches are drawn over the level.
+        type_text(&mut app, "/ERR");
+        app.redraw().unwrap();
+        let frame = recorder.last_frame().unwrap();
+        assert_eq!(highlights(&recorder, 8), vec!["ERR", "OR"]);
+        assert_eq!(frame[8][6].style.fg, Some(208));
+        assert_eq!(frame[8][9].style.fg, Some(1));
+    }
+
+    #[test]
+    fn pinned_queries_have_their_own_colors() {
+        let (mut app, recorder) = recorded(&["timeout, status=500"]);
+        type_text(&mut app, "/timeout");
+        press(&mut app, &[Key::Ctrl('p')]);
+        type_text(&mut app, "status=500");
+        press(&mut app, &[Key::Ctrl('p')]);
+        app.redraw().unwrap();
+
+        assert_eq!(app.current_query(), "");
+        assert_eq!(highlights(&recorder, 8), vec!["timeout", "status=500"]);
+        let frame = recorder.last_frame().unwrap();
+        let (first, second) = (frame[8][0].style, frame[8][9].style);
+        assert!(first.fg.is_some() && second.fg.is_some());
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn repeated_blocks_are_collapsed() {
+        let mut lines = vec!["start"];
+        for _ in 0..3 {
+            lines.extend(&["connecting", "retrying", "failed"]);
+        }
+        lines.push("end");
+        let (mut app, recorder) = recorded(&lines);
+        let blocks = find_blocks(&app.raw_buffer.lock().unwrap());
+        assert_eq!(blocks, vec![(1..10, 3)]);
+
+        press(&mut app, &[Key::Char('D')]);
+        app.redraw().unwrap();
+        assert_eq!(
+            shown_lines(&recorder),
+            vec![
+                "start",
+                "connecting",
+                "retrying",
+                "failed (block x3)",
+                "end"
+            ]
+        );
+
+        // The block under the cursor line can be expanded, and collapsed again.
+        press(&mut app, &[Key::Char('c'), Key::Down]);
+        assert_eq!(app.state.lock().unwrap().cursor_line, Some(1));
+        press(&mut app, &[Key::Char('z')]);
+        app.redraw().unwrap();
+        let expanded = shown_lines(&recorder);
+        assert_eq!(expanded.len(), 9);
+        assert_eq!(expanded[..3], ["retrying", "failed", "connecting"]);
+        assert!(!expanded.iter().any(|line| line.contains("(block")));
+
+        press(&mut app, &[Key::Char('z')]);
+        app.redraw().unwrap();
+        assert_eq!(shown_lines(&recorder).len(), 5);
+    }
+
+    #[test]
+    fn errors_are_jumped_to_in_order() {
+        let mut lines: Vec<String> = (0..30).map(|i| format!("line {}", i)).collect();
+        lines[2] = "an error".to_string();
+        lines[12] = "FATAL: out of memory".to_string();
+        lines[20] = "thread panicked, panic".to_string();
+        lines[25] = "no errors".to_string();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut app = app(&lines);
+        app.redraw().unwrap();
+
+        // From the top of the buffer, the errors after the middle of the screen come first.
+        press(&mut app, &[Key::Home]);
+        let mut found = Vec::new();
+        for _ in 0..4 {
+            press(&mut app, &[Key::Char('e')]);
+            app.redraw().unwrap();
+            found.push(app.state.lock().unwrap().current_match.unwrap());
+        }
+        assert_eq!(found, vec![12, 20, 2, 12]);
+
+        press(&mut app, &[Key::Char('E')]);
+        assert_eq!(app.state.lock().unwrap().current_match, Some(2));
+    }
+
+    #[test]
+    fn gzip_input_is_decompressed() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"first\nsecond\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut app = App::new(Vec::new()).gzip_input(io::Cursor::new(compressed.clone()));
+        app.read_all().unwrap();
+        assert_eq!(*app.lines(), vec!["first", "second"]);
+
+        // A stream that is cut off is an error.
+        let truncated = compressed[..compressed.len() - 10].to_vec();
+        let mut app = App::new(Vec::new()).gzip_input(io::Cursor::new(truncated));
+        assert!(matches!(app.read_all(), Err(AppError::ReadError(_))));
+
+        let mut app = App::new(Vec::new()).gzip_input(&b"not compressed"[..]);
+        assert!(matches!(app.read_all(), Err(AppError::ReadError(_))));
+    }
+
+    #[test]
+    fn committed_query_stays_highlighted() {
+        let (mut app, recorder) = recorded(&["an error", "fine"]);
+        type_text(&mut app, "/error");
+        press(&mut app, &[Key::Char('\n')]);
+        app.redraw().unwrap();
+        assert_eq!(app.mode(), state::Mode::Normal);
+        assert_eq!(highlighted(&recorder, 7), "error");
+
+        // Scrolling keeps the highlights.
+        press(&mut app, &[Key::Up]);
+        app.redraw().unwrap();
+        assert_eq!(highlighted(&recorder, 7), "error");
+
+        // Leaving with Esc doesn't.
+        type_text(&mut app, "/");
+        press(&mut app, &[Key::Esc]);
+        app.redraw().unwrap();
+        assert_eq!(app.mode(), state::Mode::Normal);
+        assert_eq!(highlighted(&recorder, 7), "");
+    }
+
+    #[test]
+    fn statistics_of_the_buffer() {
+        let buffer: VecDeque<String> = ["short", "a longer line", "全角"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(
+            stats(&buffer, Some(1)),
+            vec![
+                "Lines:         3",
+                "Bytes:         27",
+                "Longest line:  13 columns",
+                "Matches:       1 (33.3%)",
+            ]
+        );
+        assert_eq!(stats(&VecDeque::new(), None)[0], "Lines:         0");
+
+        // The statistics are shown with s, and any key closes them.
+        let lines: Vec<&str> = buffer.iter().map(String::as_str).collect();
+        let (mut app, recorder) = recorded(&lines);
+        press(&mut app, &[Key::Char('s')]);
+        app.redraw().unwrap();
+        assert!(rows(&recorder)
+            .iter()
+            .any(|row| row.contains("Bytes:         27")));
+        press(&mut app, &[Key::Char('x')]);
+        assert_eq!(app.mode(), state::Mode::Normal);
+    }
+
+    #[test]
+    fn footer_is_colored_across_the_screen() {
+        let theme = Theme {
+            footer_fg: Some(AnsiValue(15)),
+            footer_bg: Some(AnsiValue(4)),
+            footer_invert: false,
+            ..Theme::default()
+        };
+        let (app, recorder) = recorded(&["a line"]);
+        let mut app = app.theme(theme);
+        app.redraw().unwrap();
+
+        let footer = &recorder.last_frame().unwrap()[9];
+        assert_eq!(footer.len(), 40);
+        assert!(footer
+            .iter()
+            .all(|cell| cell.style.fg == Some(15) && cell.style.bg == Some(4)));
+
+        // Inverted, the footer uses the colors of the terminal.
+        let theme = Theme {
+            footer_invert: true,
+            ..Theme::default()
+        };
+        let (app, recorder) = recorded(&["a line"]);
+        let mut app = app.theme(theme);
+        app.redraw().unwrap();
+        let footer = &recorder.last_frame().unwrap()[9];
+        assert!(footer
+            .iter()
+            .all(|cell| cell.style.inverted && cell.style.bg.is_none()));
+    }
+
+    #[test]
+    fn tiny_screens_are_drawn() {
+        let recorder = Recorder::new();
+        let mut app = App::new(Vec::new()).size(40, 0).renderer(recorder.clone());
+        app.push_line("a line", None);
+        assert!(app.redraw().is_ok());
+        assert!(recorder.frames().is_empty());
+
+        // With a single row, only the footer is drawn.
+        let mut app = App::new(Vec::new()).size(40, 1).renderer(recorder.clone());
+        app.push_line("a line", None);
+        press(&mut app, &[Key::Up, Key::Char('n')]);
+        assert!(app.redraw().is_ok());
+        let rows = rows(&recorder);
+        assert_eq!(rows.len(), 1);
+        assert!(!rows[0].contains("a line"), "{}", rows[0]);
+        assert!(rows[0].contains("[done]"), "{}", rows[0]);
+    }
+
+    #[test]
+    fn whitespace_is_made_visible() {
+        let (mut app, recorder) = recorded(&["two  words "]);
+        press(&mut app, &[Key::Char('W')]);
+        type_text(&mut app, "/words");
+        app.redraw().unwrap();
+
+        assert_eq!(rows(&recorder)[8], "two··words·");
+        assert_eq!(*app.lines(), vec!["two  words "]);
+        let frame = recorder.last_frame().unwrap();
+        assert_eq!(frame[8][3].style.fg, Some(8));
+        assert_eq!(frame[8][10].style.fg, Some(8));
+        assert_eq!(highlights(&recorder, 8)[1], "words");
+
+        // Control characters are shown as their pictures.
+        assert_eq!(show_whitespace("a\tb\r", &[]).0, "a→b␍");
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn lines_are_read_from_a_stream() {
+        let lines = vec!["first".to_string(), "second".to_string()];
+        let mut app = App::new(Vec::new()).stream(futures::stream::iter(lines));
+        app.read_all().unwrap();
+
+        assert_eq!(*app.lines(), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn only_the_tail_of_the_input_is_kept() {
+        let input: String = (1..=500).map(|i| format!("line {}\n", i)).collect();
+        let (app, recorder) = recorded(&[]);
+        let mut app = app.tail(50).input(io::Cursor::new(input));
+        let (keys, events) = channel::unbounded();
+        let quitter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            keys.send(Event::Key(Key::Home)).unwrap();
+            keys.send(Event::Key(Key::Ctrl('c'))).unwrap();
+        });
+        app.start(events).unwrap();
+        quitter.join().unwrap();
+
+        let lines = app.lines();
+        assert_eq!(lines.len(), 50);
+        assert_eq!(lines[0], "line 451");
+        assert_eq!(lines[49], "line 500");
+        drop(lines);
+
+        // Scrolling back stops at the first line that was kept.
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[0], "line 451");
+    }
+
+    #[test]
+    fn last_line_is_right_above_the_footer() {
+        let lines: Vec<String> = (1..=30).map(|i| format!("line {}", i)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let (mut app, recorder) = recorded(&lines);
+        app.redraw().unwrap();
+        let rows = rows(&recorder);
+        assert_eq!(rows.len(), 10);
+        assert_eq!(rows[0], "line 22");
+        assert_eq!(rows[8], "line 30");
+        assert!(rows[9].contains("30/30"), "{}", rows[9]);
+
+        // On the terminal, rows are counted from 1.
+        let output = Output::default();
+        let mut app = App::new(output.clone()).size(40, 10);
+        for line in &lines {
+            app.push_line(line, None);
+        }
+        app.redraw().unwrap();
+        let row = |row| format!("{}{}", cursor::Goto(1, row), clear::CurrentLine);
+        assert!(output.text().contains(&format!("{}line 30", row(9))));
+        assert!(output
+            .text()
+            .contains(&format!("{}{}", row(10), Theme::default().footer_start())));
+    }
+
+    #[test]
+    fn alternate_screen_is_used_while_running() {
+        let output = Output::default();
+        let mut app = App::new(output.clone()).size(40, 10).input(io::empty());
+        start(&mut app, &[]).unwrap();
+        let text = output.text();
+        assert!(text.starts_with(&terminal::start_sequence()), "{:?}", text);
+        assert!(text.contains(&screen::ToAlternateScreen.to_string()));
+        assert!(!text.contains(&screen::ToMainScreen.to_string()));
+
+        // The main screen is back once the app is gone.
+        drop(app);
+        let text = output.text();
+        assert!(text.ends_with(&terminal::reset_sequence()), "{:?}", text);
+        assert!(text.ends_with(&screen::ToMainScreen.to_string()));
+    }
+
+    #[test]
+    fn replacement_is_previewed() {
+        let (mut app, recorder) = recorded(&["took 12 ms", "no digits"]);
+        type_text(&mut app, r"R(\d+)");
+        press(&mut app, &[Key::Char('\t')]);
+        type_text(&mut app, "N");
+        app.redraw().unwrap();
+
+        assert_eq!(shown_lines(&recorder), vec!["took N ms", "no digits"]);
+        assert_eq!(highlighted(&recorder, 7), "N");
+        assert_eq!(*app.lines(), vec!["took 12 ms", "no digits"]);
+
+        // Leaving replace mode shows the lines as they are.
+        press(&mut app, &[Key::Esc]);
+        app.redraw().unwrap();
+        assert_eq!(shown_lines(&recorder), vec!["took 12 ms", "no digits"]);
+    }
+
+    #[test]
+    fn gutter_shows_when_lines_were_read() {
+        let started = UNIX_EPOCH + Duration::from_secs(1000);
+        let arrival = started + Duration::from_millis(1300);
+        assert_eq!(timestamp(Timestamps::Off, arrival, started), None);
+        assert_eq!(
+            timestamp(Timestamps::Elapsed, arrival, started).as_deref(),
+            Some("+1.3s")
+        );
+        let clock = timestamp(Timestamps::Clock, arrival, started).unwrap();
+        let digits = |s: &str| s.len() == 2 && s.chars().all(|c| c.is_ascii_digit());
+        assert!(
+            clock.split(':').all(digits) && clock.len() == 8,
+            "{}",
+            clock
+        );
+
+        let (app, recorder) = recorded(&[]);
+        let mut app = app.timestamps(Timestamps::Elapsed);
+        app.started = started;
+        app.push_line("from a file", None);
+        app.push_line("read live", Some(arrival));
+        app.redraw().unwrap();
+
+        // Lines that were read from a file don't have a time.
+        assert_eq!(
+            shown_lines(&recorder),
+            vec!["      from a file", "+1.3s read live"]
+        );
+    }
+
+    #[test]
+    fn tab_pans_to_every_match_in_the_line() {
+        let mut wide = vec!['.'; 150];
+        for &column in &[10, 60, 110] {
+            wide[column] = 'x';
+        }
+        let wide: String = wide.into_iter().collect();
+        let mut app = app(&[&wide, "no match", "x marks the spot"]);
+        type_text(&mut app, "/x");
+        press(&mut app, &[Key::Char('\n')]);
+        app.redraw().unwrap();
+
+        let mut panned = Vec::new();
+        for _ in 0..4 {
+            press(&mut app, &[Key::Char('\t')]);
+            let state = app.state.lock().unwrap();
+            panned.push((state.current_match, state.h_offset));
+        }
+        assert_eq!(
+            panned,
+            vec![(Some(0), 10), (Some(0), 60), (Some(0), 110), (Some(2), 0)]
+        );
+
+        press(&mut app, &[Key::BackTab]);
+        assert_eq!(app.state.lock().unwrap().h_offset, 110);
+    }
+
+    #[test]
+    fn quiet_start_draws_once_the_input_is_read() {
+        let input: String = (1..=100).map(|i| format!("line {}\n", i)).collect();
+        let (app, recorder) = recorded(&[]);
+        let mut app = app.quiet_start(true).input(io::Cursor::new(input));
+
+        let (keys, events) = channel::unbounded();
+        let quitter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            keys.send(Event::Key(Key::Ctrl('c'))).unwrap();
+        });
+        app.start(events).unwrap();
+        quitter.join().unwrap();
+
+        assert_eq!(recorder.frames().len(), 1);
+        assert_eq!(rows(&recorder)[8], "line 100");
+    }
+
+    #[test]
+    fn lines_stick_to_the_anchored_edge() {
+        let lines: Vec<String> = (1..=30).map(|i| format!("line {}", i)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+
+        let (app, recorder) = recorded(&lines);
+        let mut app = app.anchor(Anchor::Top);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[0], "line 1");
+        assert_eq!(rows(&recorder)[8], "line 9");
+        press(&mut app, &[Key::Down]);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[0], "line 2");
+
+        let (mut app, recorder) = recorded(&lines);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[0], "line 22");
+        assert_eq!(rows(&recorder)[8], "line 30");
+
+        // When the lines don't fill the screen, they start at the anchored edge.
+        let (app, recorder) = recorded(&lines[..3]);
+        let mut app = app.anchor(Anchor::Top);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[..4], ["line 1", "line 2", "line 3", ""]);
+        let (mut app, recorder) = recorded(&lines[..3]);
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[5..9], ["", "line 1", "line 2", "line 3"]);
+    }
+
+    #[test]
+    fn slow_search_is_cut_short() {
+        let line = format!("{}c", "ab".repeat(2000));
+        let lines = vec![line.as_str(); 1000];
+        let (app, recorder) = recorded(&lines);
+        let mut app = app.highlight_budget(Duration::from_millis(1));
+        type_text(&mut app, r"/(a|b|ab|ba)+(\w+)*x");
+
+        let started = Instant::now();
+        app.redraw().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(app.too_slow);
+        assert_eq!(app.match_count, None);
+        assert!(app.footer(80).0.contains("(search too slow)"));
+        assert_eq!(highlighted(&recorder, 8), "");
+    }
+
+    #[test]
+    fn unchanged_buffer_is_not_searched_again() {
+        let mut app = app(&["foo"; 10]);
+        type_text(&mut app, "/foo");
+        app.redraw().unwrap();
+        assert_eq!(app.match_count, Some(10));
+
+        // Had the buffer been searched again, nothing would match anymore.
+        app.regex = Some(Matcher::new(&["bar".to_string()]).unwrap());
+        app.spinner += 1;
+        app.redraw().unwrap();
+        assert_eq!(app.match_count, Some(10));
+
+        app.push_line("foo", None);
+        app.redraw().unwrap();
+        assert_eq!(app.match_count, Some(0));
+    }
+
+    #[test]
+    fn excluded_lines_are_hidden() {
+        let lines = [
+            "error: disk full",
+            "error: timeout",
+            "timeout",
+            "fine",
+            "another error",
+        ];
+        let (mut app, recorder) = recorded(&lines);
+        type_text(&mut app, "xtimeout\n/error");
+        press(&mut app, &[Key::Ctrl('o')]);
+        app.redraw().unwrap();
+
+        assert_eq!(
+            shown_lines(&recorder),
+            vec!["error: disk full", "another error"]
+        );
+        assert_eq!(highlights(&recorder, 8), vec!["error"]);
+        let footer = app.footer(80).0;
+        assert!(
+            footer.contains("/error") && footer.contains("timeout"),
+            "{}",
+            footer
+        );
+    }
+
+    // An output that fails every write with the error.
+    struct Failing(io::ErrorKind);
+
+    impl io::Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(self.0.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(self.0.into())
+        }
+    }
+
+    #[test]
+    fn closed_output_stops_the_app() {
+        let mut app = App::new(Failing(io::ErrorKind::BrokenPipe))
+            .size(40, 10)
+            .input(&b"a line\n"[..]);
+        assert!(start(&mut app, &[Key::Char('j')]).is_ok());
+
+        // A slow output only skips the frame, other errors are reported.
+        let mut app = App::new(Failing(io::ErrorKind::WouldBlock)).size(40, 10);
+        app.push_line("a line", None);
+        assert!(app.redraw().is_ok());
+        let mut app = App::new(Failing(io::ErrorKind::PermissionDenied)).size(40, 10);
+        assert!(matches!(app.redraw(), Err(AppError::WriteError(_))));
+    }
+
+    #[test]
+    fn lines_are_transformed_before_they_are_shown() {
+        let (app, recorder) = recorded(&[]);
+        let mut app = app.line_transform(|line| line.to_uppercase());
+        app.push_line("a quiet line", None);
+        type_text(&mut app, "/QUIET");
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], "A QUIET LINE");
+        assert_eq!(highlighted(&recorder, 8), "QUIET");
+
+        // Searching the original lines, only the lines as they were read match.
+        let (app, recorder) = recorded(&[]);
+        let mut app = app
+            .line_transform(|line| line.to_uppercase())
+            .search_original(true);
+        app.push_line("a quiet line", None);
+        type_text(&mut app, "/quiet");
+        app.redraw().unwrap();
+        assert_eq!(rows(&recorder)[8], "A QUIET LINE");
+        assert_eq!(app.match_count, Some(1));
+    }
+
+    #[test]
+    fn current_match_stands_out() {
+        let (mut app, recorder) = recorded(&["foo here", "foo and foo"]);
+        type_text(&mut app, "/foo");
+        press(&mut app, &[Key::Char('\n')]);
+        app.redraw().unwrap();
+        press(&mut app, &[Key::Char('n')]);
+        app.redraw().unwrap();
+        let current = app.state.lock().unwrap().current_match.unwrap();
+
+        // The current match has the colors of the other matches swapped.
+        let theme = Theme::default();
+        let frame = recorder.last_frame().unwrap();
+        let emphasized: Vec<(usize, usize)> = [(7, 0), (8, 0), (8, 8)]
+            .iter()
+            .copied()
+            .filter(|&(row, column)| {
+                let style = frame[row][column].style;
+                assert!(style.fg.is_some());
+                style.bg == theme.match_fg.map(|color| color.0)
+            })
+            .collect();
+        assert_eq!(emphasized, vec![(7 + current, 0)]);
+    }
+
+    #[test]
+    fn screen_is_redrawn_when_it_is_resized() {
+        let lines: Vec<String> = (1..=20).map(|i| format!("line {}", i)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let (app, recorder) = recorded(&lines);
+        let (resize, resizes) = channel::unbounded();
+        let mut app = app.input(io::empty()).resizes(resizes);
+
+        let (keys, events) = channel::unbounded();
+        let sender = thread::spawn(move || {
+            let pause = || thread::sleep(Duration::from_millis(100));
+            pause();
+            keys.send(Event::Key(Key::Home)).unwrap();
+            pause();
+            resize.send((60, 30)).unwrap();
+            pause();
+            keys.send(Event::Key(Key::Ctrl('c'))).unwrap();
+        });
+        app.start(events).unwrap();
+        sender.join().unwrap();
+
+        // Every line fits on the larger screen, so there's nothing left to scroll.
+        let rows = rows(&recorder);
+        assert_eq!(rows.len(), 30);
+        assert_eq!(rows[9], "line 1");
+        assert_eq!(rows[28], "line 20");
+        assert_eq!(recorder.last_frame().unwrap()[29].len(), 60);
+        assert_eq!(app.state.lock().unwrap().scroll_offset, 0);
+    }
+
+    #[test]
+    fn enter_commits_the_query_and_then_jumps_from_match_to_match() {
+        let mut lines: Vec<String> = (0..100).map(|i| format!("line {}", i)).collect();
+        for &i in &[10, 50, 95] {
+            lines[i] = format!("match {}", i);
+        }
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let (mut app, recorder) = recorded(&lines);
+        type_text(&mut app, "/match");
+        app.redraw().unwrap();
+
+        // The first Enter leaves search mode, the matches stay highlighted.
+        press(&mut app, &[Key::Char('\n')]);
+        app.redraw().unwrap();
+        assert_eq!(app.mode(), state::Mode::Normal);
+        let row = rows(&recorder)
+            .iter()
+            .position(|row| row == "match 95")
+            .unwrap();
+        assert_eq!(highlighted(&recorder, row), "match");
+        assert_eq!(app.state.lock().unwrap().current_match, None);
+
+        // Searching for the same query again, every Enter jumps to the next match.
+        press(&mut app, &[Key::Char('/')]);
+        let mut found = Vec::new();
+        for _ in 0..3 {
+            press(&mut app, &[Key::Char('\n')]);
+            app.redraw().unwrap();
+            found.push(app.state.lock().unwrap().current_match.unwrap());
+            assert!(rows(&recorder).contains(&format!("match {}", found.last().unwrap())));
+        }
+        assert_eq!(found, vec![10, 50, 95]);
+        assert_eq!(app.mode(), state::Mode::Search);
+        assert_eq!(app.current_query(), "match");
+    }
+
+    #[test]
+    fn context_is_shown_around_the_matches() {
+        let lines = [
+            "a", "b", "match 1", "c", "d", "e", "match 2", "match 3", "f", "g",
+        ];
+        let (app, recorder) = recorded(&lines);
+        let mut app = app.context(1);
+        type_text(&mut app, "/match");
+        press(&mut app, &[Key::Ctrl('o')]);
+        app.redraw().unwrap();
+
+        // Windows that overlap are merged, the others are separated.
+        assert_eq!(
+            shown_lines(&recorder),
+            vec!["b", "match 1", "c", "--", "e", "match 2", "match 3", "f"]
+        );
+
+        // Without the filter, every line is shown anyway.
+        press(&mut app, &[Key::Ctrl('o')]);
+        app.redraw().unwrap();
+        assert!(!shown_lines(&recorder).contains(&"--".to_string()));
+    }
+
+    #[test]
+    fn footer_shows_whether_input_is_coming_in() {
+        let (lines, source) = channel::unbounded();
+        let (keys, events) = channel::unbounded();
+        let (app, recorder) = recorded(&[]);
+        let mut app = app.source(ChannelSource(source));
+
+        let footers = recorder.clone();
+        let sender = thread::spawn(move || {
+            let pause = || thread::sleep(Duration::from_millis(150));
+            let footer = || rows(&footers)[9].clone();
+            lines.send("a line".to_string()).unwrap();
+            pause();
+            let streaming = footer();
+            drop(lines);
+            pause();
+            keys.send(Event::Key(Key::Ctrl('c'))).unwrap();
+            streaming
+        });
+        app.start(events).unwrap();
+
+        let streaming = sender.join().unwrap();
+        assert!(streaming.contains("[streaming "), "{}", streaming);
+        assert!(!rows(&recorder)[9].contains("[streaming "));
+        assert!(rows(&recorder)[9].contains("[done] "));
+    }
+
+    #[test]
+    fn footer_starts_with_the_prompt() {
+        let mut app = app(&["a line"]);
+        type_text(&mut app, "/err");
+        let (footer, cursor) = app.footer(40);
+        assert!(footer.starts_with("/err "), "{}", footer);
+        assert!(footer.ends_with("Search"), "{}", footer);
+        assert_eq!(cursor, Some(4));
+
+        let mut app = App::new(Vec::new())
+            .size(40, 10)
+            .search_prompt("search: ")
+            .command_prompt("> ");
+        type_text(&mut app, "/err");
+        assert!(app.footer(40).0.starts_with("search: err "));
+        press(&mut app, &[Key::Esc]);
+        type_text(&mut app, ":12");
+        assert!(app.footer(40).0.starts_with("> 12 "));
+        assert_eq!(app.footer(40).0.width(), 40);
+    }
+
+    #[test]
+    fn state_is_read_through_the_accessors() {
+        let mut app = App::new(Vec::new())
+            .size(40, 10)
+            .input(&b"first\nsecond\n"[..]);
+        app.read_all().unwrap();
+        assert_eq!(*app.lines(), vec!["first", "second"]);
+        assert_eq!(app.mode(), state::Mode::Normal);
+        assert_eq!(app.current_query(), "");
+
+        type_text(&mut app, "/sec");
+        assert_eq!(app.mode(), state::Mode::Search);
+        assert_eq!(app.current_query(), "sec");
+    }
+
+    #[test]
+    fn underline_wraps_the_match() {
+        let theme = Theme {
+            match_underline: true,
+            ..Theme::default()
+        };
+        let output = Output::default();
+        let mut app = App::new(output.clone()).size(40, 10).theme(theme);
+        app.push_line("an error here", None);
+        type_text(&mut app, "/error");
+        app.redraw().unwrap();
+
+        assert!(theme.match_start().ends_with(&style::Underline.to_string()));
+        let line = format!("an {}error{} here", theme.match_start(), theme::reset());
+        assert!(output.text().contains(&line), "{:?}", output.text());
+    }
+
+    #[test]
+    fn matches_are_written_as_json() {
+        let output = Output::default();
+        let mut app = App::new(output.clone()).input(&b"fine\nan error\nfine again\n"[..]);
+        app.run_json("error", SearchOptions::default()).unwrap();
+        let json: serde_json::Value = serde_json::from_str(output.text().trim_end()).unwrap();
+        assert_eq!(json["line_number"], 2);
+        assert_eq!(json["line"], "an error");
+        assert_eq!(json["spans"], serde_json::json!([[3, 8]]));
+
+        // Without matches, nothing is written at all.
+        let output = Output::default();
+        let mut app = App::new(output.clone()).input(&b"fine\n"[..]);
+        app.run_json("error", SearchOptions::default()).unwrap();
+        assert_eq!(output.text(), "");
     }
 }
@@ -0,0 +1,90 @@
+// Copying text to the clipboard. With the `clipboard` feature the system clipboard is used. When
+// that isn't available, e.g. over SSH or without the feature, the text is sent to the terminal in
+// an OSC 52 escape sequence instead. Many terminals put that text on the clipboard.
+use std::io::{self, Write};
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Default)]
+pub struct Clipboard {
+    // On some systems the copied text is only available while the clipboard is open, so it's
+    // kept open once it has been opened.
+    #[cfg(feature = "clipboard")]
+    system: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        Clipboard::default()
+    }
+
+    // Copy the text, writing the escape sequence to the output if the system clipboard can't be
+    // used.
+    pub fn copy<W: Write>(&mut self, text: &str, output: &mut W) -> io::Result<()> {
+        #[cfg(feature = "clipboard")]
+        {
+            if self.system.is_none() {
+                self.system = arboard::Clipboard::new().ok();
+            }
+            if let Some(ref mut system) = self.system {
+                if system.set_text(text).is_ok() {
+                    return Ok(());
+                }
+            }
+        }
+
+        write!(output, "{}", osc52(text))?;
+        output.flush()
+    }
+}
+
+// Return the OSC 52 escape sequence that copies the text to the clipboard.
+pub fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+
+        // A chunk of n bytes is encoded in n + 1 characters, the rest is padding.
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_encoded_in_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("a line ✓".as_bytes()), "YSBsaW5lIOKckw==");
+        assert_eq!(osc52("foo"), "\x1b]52;c;Zm9v\x07");
+    }
+
+    #[cfg(not(feature = "clipboard"))]
+    #[test]
+    fn text_is_copied_by_the_terminal() {
+        let mut output = Vec::new();
+        Clipboard::new().copy("an error", &mut output).unwrap();
+        assert_eq!(output, b"\x1b]52;c;YW4gZXJyb3I=\x07");
+    }
+}
@@ -0,0 +1,284 @@
+// Options can be set in a TOML file, so they don't have to be passed every time. This is an
+// example with all options:
+//
+//     match_color = 1
+//     match_background = 226
+//     match_bold = false
+//     match_underline = true
+//     match_reverse = false
+//     footer_color = 15
+//     footer_background = 4
+//     footer_invert = false
+//     cursor_line_background = 236
+//     case_insensitive = false
+//     smart_case = true
+//     max_lines = 100000
+//     tab_width = 4
+//     search_prompt = "/"
+//     command_prompt = ":"
+//     quiet_start = false
+//     highlight_budget = 100
+//     wrap = true
+//     line_numbers = true
+//     timestamps = "clock"
+//     highlight_levels = true
+//     wrap_search = false
+//     context = 2
+//     scroll_step = 3
+//     placement = "top"
+//     anchor = "bottom"
+//     error_pattern = '(?i)\b(error|fatal|panic)\b'
+//
+//     [[bind]]
+//     mode = "normal"
+//     key = "Ctrl-f"
+//     action = "page_down"
+//
+// Colors are numbers of the 256 color palette.
+use crate::app;
+use crate::error::AppError;
+use crate::keymap::{self, Action, KeyMap};
+use crate::state::{Anchor, Mode, Placement, Timestamps};
+use regex::Regex;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Options that aren't set in the file are `None`, or empty, and leave the option as it is.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub match_color: Option<u8>,
+    pub match_background: Option<u8>,
+
+    // Draw matches in bold, underlined or with the colors inverted, besides in their colors.
+    pub match_bold: Option<bool>,
+    pub match_underline: Option<bool>,
+    pub match_reverse: Option<bool>,
+    pub footer_color: Option<u8>,
+    pub footer_background: Option<u8>,
+
+    // Draw the footer in the inverted colors of the terminal, instead of in the footer colors.
+    pub footer_invert: Option<bool>,
+    pub cursor_line_background: Option<u8>,
+    pub case_insensitive: Option<bool>,
+    pub smart_case: Option<bool>,
+    pub max_lines: Option<usize>,
+    pub tab_width: Option<usize>,
+
+    // What is shown in front of the query while searching, and in front of commands.
+    pub search_prompt: Option<String>,
+    pub command_prompt: Option<String>,
+
+    // Don't draw anything until the input has been read, or a key is pressed.
+    pub quiet_start: Option<bool>,
+
+    // How long highlighting the lines on the screen may take, in milliseconds.
+    pub highlight_budget: Option<u64>,
+    pub wrap: Option<bool>,
+    pub line_numbers: Option<bool>,
+
+    // Show when lines were read: "off", "clock" or "elapsed".
+    pub timestamps: Option<Timestamps>,
+    pub highlight_levels: Option<bool>,
+
+    // Whether jumping past the last match continues at the first match. It's on when it isn't set.
+    pub wrap_search: Option<bool>,
+
+    // The number of lines around a match that are shown in filter mode, like `grep -C`.
+    pub context: Option<usize>,
+
+    // The number of lines that Up and Down scroll, and where lines that are jumped to end up:
+    // "top", "center" or "bottom".
+    pub scroll_step: Option<usize>,
+    pub placement: Option<Placement>,
+
+    // The edge of the screen lines stick to: "top" or "bottom".
+    pub anchor: Option<Anchor>,
+
+    // The regex that lines that are errors match, for jumping between errors.
+    pub error_pattern: Option<String>,
+
+    // Key bindings that are added to the default ones, replacing the default binding of a key.
+    pub bind: Vec<BindingConfig>,
+
+    // Problems with the file that don't stop it from being used, like unknown options.
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+// A misspelled key in a binding is an error, instead of a binding that silently does nothing.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BindingConfig {
+    pub mode: Mode,
+
+    // The name of the key as it's shown on the help screen, e.g. `Ctrl-f` or `PgDn`.
+    pub key: String,
+    pub action: Action,
+}
+
+// The options that are recognized at the top level of the file.
+const OPTIONS: &[&str] = &[
+    "match_color",
+    "match_background",
+    "match_bold",
+    "match_underline",
+    "match_reverse",
+    "footer_color",
+    "footer_background",
+    "footer_invert",
+    "cursor_line_background",
+    "case_insensitive",
+    "smart_case",
+    "max_lines",
+    "tab_width",
+    "search_prompt",
+    "command_prompt",
+    "quiet_start",
+    "highlight_budget",
+    "wrap",
+    "line_numbers",
+    "timestamps",
+    "highlight_levels",
+    "wrap_search",
+    "context",
+    "scroll_step",
+    "placement",
+    "anchor",
+    "error_pattern",
+    "bind",
+];
+
+impl Config {
+    // Return the path of the configuration file, following the XDG Base Directory Specification.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+
+        Some(dir.join("kurzhozen").join("config.toml"))
+    }
+
+    // Read the configuration from the file. A missing file isn't an error, it results in the
+    // default configuration.
+    pub fn load(path: &Path) -> app::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(config) => Config::parse(&config),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(AppError::ReadError(e)),
+        }
+    }
+
+    // Parse the configuration. Unknown options don't cause an error, they end up in `warnings`.
+    pub fn parse(config: &str) -> app::Result<Self> {
+        let value: toml::Value = toml::from_str(config).map_err(AppError::ConfigError)?;
+
+        let mut warnings = Vec::new();
+        if let Some(table) = value.as_table() {
+            for option in table.keys() {
+                if !OPTIONS.contains(&option.as_str()) {
+                    warnings.push(format!("Unknown option: {}", option));
+                }
+            }
+        }
+
+        let mut config: Config = value.try_into().map_err(AppError::ConfigError)?;
+        for binding in &config.bind {
+            if keymap::parse_key(&binding.key).is_none() {
+                warnings.push(format!("Unknown key: {}", binding.key));
+            }
+        }
+
+        if let Some(ref pattern) = config.error_pattern {
+            Regex::new(pattern).map_err(AppError::RegexError)?;
+        }
+
+        config.warnings = warnings;
+        Ok(config)
+    }
+
+    // Return the key map with the bindings of the configuration added to it. Bindings with a key
+    // that isn't recognized are skipped.
+    pub fn keymap(&self, mut keymap: KeyMap) -> KeyMap {
+        for binding in &self.bind {
+            if let Some(key) = keymap::parse_key(&binding.key) {
+                keymap = keymap.bind(
+                    binding.mode,
+                    key,
+                    binding.action,
+                    "Bound in the configuration file",
+                );
+            }
+        }
+        keymap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn options_are_parsed() {
+        let config = Config::parse(
+            r#"
+            match_color = 1
+            max_lines = 500
+            wrap = false
+            timestamps = "elapsed"
+            colour = 3
+
+            [[bind]]
+            mode = "normal"
+            key = "Ctrl-f"
+            action = "page_down"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.match_color, Some(1));
+        assert_eq!(config.max_lines, Some(500));
+        assert_eq!(config.wrap, Some(false));
+        assert_eq!(config.timestamps, Some(Timestamps::Elapsed));
+        assert_eq!(config.line_numbers, None);
+        assert_eq!(config.warnings, vec!["Unknown option: colour"]);
+
+        let keymap = config.keymap(KeyMap::default());
+        assert_eq!(
+            keymap.lookup(Mode::Normal, termion::event::Key::Ctrl('f')),
+            Some(Action::PageDown)
+        );
+
+        // Unlike unknown options, unknown keys in a binding are rejected.
+        let unknown = Config::parse(
+            r#"
+            [[bind]]
+            mode = "normal"
+            key = "Ctrl-f"
+            action = "page_down"
+            count = 2
+            "#,
+        );
+        assert!(matches!(unknown, Err(AppError::ConfigError(_))));
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert!(matches!(
+            Config::parse("max_lines = \"many\""),
+            Err(AppError::ConfigError(_))
+        ));
+        assert!(matches!(
+            Config::parse("error_pattern = \"(\""),
+            Err(AppError::RegexError(_))
+        ));
+
+        let missing = env::temp_dir().join(format!("kurzhozen-missing-{}.toml", process::id()));
+        assert_eq!(Config::load(&missing).unwrap().max_lines, None);
+    }
+}
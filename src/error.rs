@@ -4,15 +4,30 @@ use std::io;
 
 #[derive(Debug)]
 pub enum AppError {
-    InputError(String),
-    IOError(io::Error),
+    // Reading the input failed.
+    ReadError(io::Error),
+
+    // Writing to the output failed.
+    WriteError(io::Error),
+
+    // A query couldn't be compiled into a regex.
+    RegexError(regex::Error),
+
+    // The terminal couldn't be set up.
+    TerminalError(io::Error),
+
+    // The configuration file isn't valid.
+    ConfigError(toml::de::Error),
 }
 
 impl fmt::Display for AppError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            AppError::InputError(ref s) => write!(f, "InputError: {}", s),
-            AppError::IOError(ref e) => e.fmt(f),
+            AppError::ReadError(ref e) => write!(f, "Failed to read input: {}", e),
+            AppError::WriteError(ref e) => write!(f, "Failed to write output: {}", e),
+            AppError::RegexError(ref e) => write!(f, "Invalid regex: {}", e),
+            AppError::TerminalError(ref e) => write!(f, "Failed to set up the terminal: {}", e),
+            AppError::ConfigError(ref e) => write!(f, "Invalid configuration: {}", e),
         }
     }
 }
@@ -20,14 +35,49 @@ impl fmt::Display for AppError {
 impl error::Error for AppError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
-            AppError::InputError(_) => None,
-            AppError::IOError(ref e) => Some(e),
+            AppError::ReadError(ref e)
+            | AppError::WriteError(ref e)
+            | AppError::TerminalError(ref e) => Some(e),
+            AppError::RegexError(ref e) => Some(e),
+            AppError::ConfigError(ref e) => Some(e),
         }
     }
 }
 
-impl From<io::Error> for AppError {
-    fn from(err: io::Error) -> AppError {
-        AppError::IOError(err)
+impl From<regex::Error> for AppError {
+    fn from(err: regex::Error) -> AppError {
+        AppError::RegexError(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Matcher;
+    use error::Error;
+
+    #[test]
+    fn errors_tell_what_failed() {
+        let gone = || io::Error::other("gone");
+        let read = AppError::ReadError(gone());
+        assert_eq!(read.to_string(), "Failed to read input: gone");
+        assert_eq!(read.source().unwrap().to_string(), "gone");
+        assert_eq!(
+            AppError::WriteError(gone()).to_string(),
+            "Failed to write output: gone"
+        );
+        assert_eq!(
+            AppError::TerminalError(gone()).to_string(),
+            "Failed to set up the terminal: gone"
+        );
+
+        let invalid = Matcher::new(&["(".to_string()]).err().unwrap();
+        let regex = AppError::from(invalid);
+        assert!(regex.to_string().starts_with("Invalid regex: "));
+        assert!(regex.source().is_some());
+
+        let config = AppError::ConfigError(toml::from_str::<toml::Value>("a =").unwrap_err());
+        assert!(config.to_string().starts_with("Invalid configuration: "));
+        assert!(config.source().is_some());
     }
 }
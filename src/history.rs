@@ -0,0 +1,46 @@
+// The search history is stored in a file, one query per line, so that it survives restarts.
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Return the path of the history file, following the XDG Base Directory Specification.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+
+    Some(dir.join("kurzhozen").join("history"))
+}
+
+// Read the history from the file. A file that is missing or can't be read results in an empty
+// history, that's no reason to stop the program.
+pub fn load(path: &Path) -> Vec<String> {
+    match fs::read_to_string(path) {
+        Ok(history) => history
+            .lines()
+            .filter(|query| !query.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Add the queries to the end of the history file.
+pub fn append(path: &Path, queries: &[String]) -> io::Result<()> {
+    if queries.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for query in queries {
+        writeln!(file, "{}", query)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+// Lines read from the input are cleaned up before they're stored, so that what ends up in the
+// buffer is exactly the text that will be shown on the screen.
+use crate::app;
+use crate::error::AppError;
+#[cfg(feature = "async")]
+use futures::{executor, Stream, StreamExt};
+use std::io::BufRead;
+use std::vec;
+use unicode_width::UnicodeWidthChar;
+
+// A source of lines, like a file or STDIN.
+pub trait LineSource: Send {
+    // Return the next line, or `None` when there are no more lines.
+    fn next_line(&mut self) -> app::Result<Option<String>>;
+}
+
+// A `LineSource` that reads lines from anything that implements `BufRead`.
+pub struct Reader<R: BufRead + Send>(pub R);
+
+impl<R> LineSource for Reader<R>
+where
+    R: BufRead + Send,
+{
+    // Logs sometimes contain bytes that aren't valid UTF-8, these are replaced by '�' instead of
+    // failing.
+    fn next_line(&mut self) -> app::Result<Option<String>> {
+        let mut line = Vec::new();
+        let n = self
+            .0
+            .read_until(b'\n', &mut line)
+            .map_err(AppError::ReadError)?;
+        if n == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+    }
+}
+
+// Lines that are already in memory, e.g. `vec![...].into_iter()`.
+impl LineSource for vec::IntoIter<String> {
+    fn next_line(&mut self) -> app::Result<Option<String>> {
+        Ok(self.next())
+    }
+}
+
+// A `LineSource` that takes lines from an async stream, e.g. one reading from a socket. The
+// stream is polled on the thread that reads the input, so it must not depend on being polled from
+// within a particular runtime.
+#[cfg(feature = "async")]
+pub struct StreamSource<S>(pub S);
+
+#[cfg(feature = "async")]
+impl<S> LineSource for StreamSource<S>
+where
+    S: Stream<Item = String> + Unpin + Send,
+{
+    fn next_line(&mut self) -> app::Result<Option<String>> {
+        Ok(executor::block_on(self.0.next()))
+    }
+}
+
+// Remove the line break at the end of a line, both `\n` and `\r\n`. Other trailing whitespace
+// is kept.
+pub fn trim_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+// Remove ANSI escape sequences, like the ones used for colors, from a line. Input that has been
+// colorized by another program would otherwise confuse the width calculations and searching.
+pub fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            chars.next();
+
+            // A control sequence consists of parameter and intermediate bytes and ends with a
+            // single byte in the range from '@' to '~'.
+            for c in &mut chars {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        stripped.push(c);
+    }
+
+    stripped
+}
+
+// Replace every tab with enough spaces to reach the next tab stop. Tab stops are `tab_width`
+// columns apart.
+pub fn expand_tabs(line: &str, tab_width: usize) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+
+    for c in line.chars() {
+        if c == '\t' && tab_width > 0 {
+            let spaces = tab_width - column % tab_width;
+            expanded.push_str(&" ".repeat(spaces));
+            column += spaces;
+            continue;
+        }
+
+        expanded.push(c);
+        column += c.width().unwrap_or(0);
+    }
+
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_sequences_are_stripped() {
+        assert_eq!(strip_ansi("\x1b[31mred\x1b[0m"), "red");
+        assert_eq!(
+            strip_ansi("\x1b[1;38;5;196mbold\x1b[m and plain"),
+            "bold and plain"
+        );
+    }
+
+    #[test]
+    fn tabs_are_expanded_to_tab_stops() {
+        assert_eq!(expand_tabs("a\tb\tc", 4), "a   b   c");
+        assert_eq!(expand_tabs("abcd\tef\tg", 4), "abcd    ef  g");
+        assert_eq!(expand_tabs("全\tb", 4), "全  b");
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let mut reader = Reader(&b"good\xFFline\nnext\n"[..]);
+
+        assert_eq!(
+            reader.next_line().unwrap(),
+            Some("good\u{FFFD}line\n".to_string())
+        );
+        assert_eq!(reader.next_line().unwrap(), Some("next\n".to_string()));
+        assert_eq!(reader.next_line().unwrap(), None);
+    }
+
+    #[test]
+    fn line_endings_are_trimmed() {
+        assert_eq!(trim_line_ending("abc\r\n"), "abc");
+        assert_eq!(trim_line_ending("abc\n"), "abc");
+        assert_eq!(trim_line_ending("abc  \n"), "abc  ");
+        assert_eq!(trim_line_ending("abc\t"), "abc\t");
+    }
+}
@@ -0,0 +1,702 @@
+use crate::state::Mode;
+use serde::Deserialize;
+use termion::event::Key;
+
+// Everything the user can do by pressing a key. In the configuration file, actions are written in
+// snake case, e.g. `page_down`.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    ScrollUp,
+    ScrollDown,
+    ToggleCursorLine,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    PanLeft,
+    PanRight,
+    StartSequence,
+    NextMatch,
+    PreviousMatch,
+    NextMatchInLine,
+    PreviousMatchInLine,
+    NextError,
+    PreviousError,
+    ToggleWrap,
+    ToggleLineNumbers,
+    CycleTimestamps,
+    ToggleLevels,
+    ToggleWhitespace,
+    ToggleDedup,
+    ToggleBlocks,
+    ToggleBlock,
+    Help,
+    Stats,
+    Save,
+    CopyLine,
+    CopyQuery,
+    Search,
+    ToggleCaseInsensitive,
+    ToggleLiteral,
+    ToggleWholeWord,
+    ToggleFuzzy,
+    ToggleAllTerms,
+    ToggleAnchorStart,
+    ToggleAnchorEnd,
+    ToggleCaptures,
+    ToggleFilter,
+    ToggleInvert,
+    Pin,
+    DeleteChar,
+    DeleteWord,
+    ClearQuery,
+    CursorLeft,
+    CursorRight,
+    HistoryPrevious,
+    HistoryNext,
+    LeaveSearch,
+    CommitSearch,
+    SearchNext,
+    Command,
+    RunCommand,
+    LeaveCommand,
+    Goto,
+    RunGoto,
+    LeaveGoto,
+    Replace,
+    SwitchField,
+    RunReplace,
+    LeaveReplace,
+    Exclude,
+    CommitExclude,
+    LeaveExclude,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Binding {
+    pub mode: Mode,
+    pub key: Key,
+    pub action: Action,
+    pub description: &'static str,
+}
+
+// The default key bindings. The bindings are used both to handle key presses and to generate the
+// help screen, so the two can't get out of sync.
+pub const BINDINGS: &[Binding] = &[
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Up,
+        action: Action::ScrollUp,
+        description: "Scroll up one line, or move the cursor line up",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Down,
+        action: Action::ScrollDown,
+        description: "Scroll down one line, or move the cursor line down",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('c'),
+        action: Action::ToggleCursorLine,
+        description: "Toggle the cursor line",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::PageUp,
+        action: Action::PageUp,
+        description: "Scroll up one screen",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::PageDown,
+        action: Action::PageDown,
+        description: "Scroll down one screen",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Home,
+        action: Action::Top,
+        description: "Jump to the first line",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::End,
+        action: Action::Bottom,
+        description: "Jump to the last line and follow new input",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('g'),
+        action: Action::StartSequence,
+        description: "Press twice to jump to the first line",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('m'),
+        action: Action::StartSequence,
+        description: "Followed by a letter, mark the line at the top of the screen",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('\''),
+        action: Action::StartSequence,
+        description: "Followed by a letter, jump to the marked line",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('G'),
+        action: Action::Bottom,
+        description: "Jump to the last line and follow new input",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Left,
+        action: Action::PanLeft,
+        description: "Pan to the left",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Right,
+        action: Action::PanRight,
+        description: "Pan to the right, to read the end of long lines",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('n'),
+        action: Action::NextMatch,
+        description: "Jump to the next match",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('N'),
+        action: Action::PreviousMatch,
+        description: "Jump to the previous match",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('\t'),
+        action: Action::NextMatchInLine,
+        description: "Pan to the next match on the current line",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::BackTab,
+        action: Action::PreviousMatchInLine,
+        description: "Pan to the previous match on the current line",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('e'),
+        action: Action::NextError,
+        description: "Jump to the next error",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('E'),
+        action: Action::PreviousError,
+        description: "Jump to the previous error",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('w'),
+        action: Action::ToggleWrap,
+        description: "Toggle wrapping of long lines",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('#'),
+        action: Action::ToggleLineNumbers,
+        description: "Toggle line numbers",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('T'),
+        action: Action::CycleTimestamps,
+        description: "Show the time lines were read, the time since the start, or neither",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('H'),
+        action: Action::ToggleLevels,
+        description: "Toggle coloring log levels",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('W'),
+        action: Action::ToggleWhitespace,
+        description: "Toggle showing whitespace",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('d'),
+        action: Action::ToggleDedup,
+        description: "Toggle collapsing repeated lines",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('D'),
+        action: Action::ToggleBlocks,
+        description: "Toggle collapsing repeated blocks of lines",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('z'),
+        action: Action::ToggleBlock,
+        description: "Expand or collapse the repeated block at the cursor line",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('?'),
+        action: Action::Help,
+        description: "Show this help",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('s'),
+        action: Action::Stats,
+        description: "Show statistics, like the number of lines",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Ctrl('s'),
+        action: Action::Save,
+        description: "Save the lines that are shown to a file",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('y'),
+        action: Action::CopyLine,
+        description: "Copy the cursor line, or the current match, to the clipboard",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('Y'),
+        action: Action::CopyQuery,
+        description: "Copy the query to the clipboard",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('/'),
+        action: Action::Search,
+        description: "Start searching",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char(':'),
+        action: Action::Command,
+        description: "Enter a command: q, noh, unpin, columns or a line number",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('L'),
+        action: Action::Goto,
+        description: "Jump to a line number, or to a percentage of the lines like 50%",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('R'),
+        action: Action::Replace,
+        description: "Preview replacing a regex",
+    },
+    Binding {
+        mode: Mode::Normal,
+        key: Key::Char('x'),
+        action: Action::Exclude,
+        description: "Hide the lines matching a regex",
+    },
+    // Termion can't tell Ctrl-i apart from Tab, both are reported as '\t'.
+    Binding {
+        mode: Mode::Search,
+        key: Key::Char('\t'),
+        action: Action::ToggleCaseInsensitive,
+        description: "Toggle case insensitive search",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('f'),
+        action: Action::ToggleLiteral,
+        description: "Toggle literal search",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('b'),
+        action: Action::ToggleWholeWord,
+        description: "Toggle matching whole words only",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('z'),
+        action: Action::ToggleFuzzy,
+        description: "Toggle fuzzy search, matching the characters of the query in order",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('t'),
+        action: Action::ToggleAllTerms,
+        description: "Toggle matching all words of the query, in any order",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('a'),
+        action: Action::ToggleAnchorStart,
+        description: "Toggle only matching at the start of a line",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('e'),
+        action: Action::ToggleAnchorEnd,
+        description: "Toggle only matching at the end of a line",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('g'),
+        action: Action::ToggleCaptures,
+        description: "Toggle showing the groups captured in the first match",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('o'),
+        action: Action::ToggleFilter,
+        description: "Toggle hiding lines that don't match",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('v'),
+        action: Action::ToggleInvert,
+        description: "Toggle hiding lines that match",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('p'),
+        action: Action::Pin,
+        description: "Keep highlighting the query in its own color and start a new one",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('s'),
+        action: Action::Save,
+        description: "Save the lines that are shown to a file",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Backspace,
+        action: Action::DeleteChar,
+        description: "Delete the character in front of the cursor",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('w'),
+        action: Action::DeleteWord,
+        description: "Delete the word in front of the cursor",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Ctrl('u'),
+        action: Action::ClearQuery,
+        description: "Clear the query",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Left,
+        action: Action::CursorLeft,
+        description: "Move the cursor to the left",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Right,
+        action: Action::CursorRight,
+        description: "Move the cursor to the right",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Char('\n'),
+        action: Action::SearchNext,
+        description: "Keep highlighting the matches, or jump to the next one if searched already",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Up,
+        action: Action::HistoryPrevious,
+        description: "Recall an older query from the history",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Down,
+        action: Action::HistoryNext,
+        description: "Recall a newer query from the history",
+    },
+    Binding {
+        mode: Mode::Search,
+        key: Key::Esc,
+        action: Action::LeaveSearch,
+        description: "Stop searching",
+    },
+    Binding {
+        mode: Mode::Command,
+        key: Key::Char('\n'),
+        action: Action::RunCommand,
+        description: "Run the command",
+    },
+    Binding {
+        mode: Mode::Command,
+        key: Key::Backspace,
+        action: Action::DeleteChar,
+        description: "Delete the last character of the command",
+    },
+    Binding {
+        mode: Mode::Command,
+        key: Key::Esc,
+        action: Action::LeaveCommand,
+        description: "Cancel the command",
+    },
+    Binding {
+        mode: Mode::Goto,
+        key: Key::Char('\n'),
+        action: Action::RunGoto,
+        description: "Jump to the line, or to the percentage",
+    },
+    Binding {
+        mode: Mode::Goto,
+        key: Key::Backspace,
+        action: Action::DeleteChar,
+        description: "Delete the last digit",
+    },
+    Binding {
+        mode: Mode::Goto,
+        key: Key::Esc,
+        action: Action::LeaveGoto,
+        description: "Cancel the jump",
+    },
+    Binding {
+        mode: Mode::Replace,
+        key: Key::Char('\t'),
+        action: Action::SwitchField,
+        description: "Switch between the regex and the replacement",
+    },
+    Binding {
+        mode: Mode::Replace,
+        key: Key::Char('\n'),
+        action: Action::RunReplace,
+        description: "Save the lines with the replacement applied to a file",
+    },
+    Binding {
+        mode: Mode::Replace,
+        key: Key::Backspace,
+        action: Action::DeleteChar,
+        description: "Delete the last character",
+    },
+    Binding {
+        mode: Mode::Replace,
+        key: Key::Esc,
+        action: Action::LeaveReplace,
+        description: "Stop previewing the replacement",
+    },
+    Binding {
+        mode: Mode::Exclude,
+        key: Key::Char('\n'),
+        action: Action::CommitExclude,
+        description: "Keep hiding the lines matching the regex",
+    },
+    Binding {
+        mode: Mode::Exclude,
+        key: Key::Backspace,
+        action: Action::DeleteChar,
+        description: "Delete the last character",
+    },
+    Binding {
+        mode: Mode::Exclude,
+        key: Key::Esc,
+        action: Action::LeaveExclude,
+        description: "Show the lines matching the regex again",
+    },
+];
+
+// Maps keys to actions, per mode. The default key map contains `BINDINGS`.
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    bindings: Vec<Binding>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap {
+            bindings: BINDINGS.to_vec(),
+        }
+    }
+}
+
+impl KeyMap {
+    // Return a key map without any bindings.
+    pub fn empty() -> Self {
+        KeyMap {
+            bindings: Vec::new(),
+        }
+    }
+
+    // Bind the key to the action in the given mode, replacing the binding the key had before.
+    pub fn bind(mut self, mode: Mode, key: Key, action: Action, description: &'static str) -> Self {
+        self.bindings
+            .retain(|binding| !(binding.mode == mode && binding.key == key));
+        self.bindings.push(Binding {
+            mode,
+            key,
+            action,
+            description,
+        });
+        self
+    }
+
+    // Remove the binding of the key in the given mode.
+    pub fn unbind(mut self, mode: Mode, key: Key) -> Self {
+        self.bindings
+            .retain(|binding| !(binding.mode == mode && binding.key == key));
+        self
+    }
+
+    // Return the action that is bound to the key in the given mode.
+    pub fn lookup(&self, mode: Mode, key: Key) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.mode == mode && binding.key == key)
+            .map(|binding| binding.action)
+    }
+
+    // Return the lines of the help screen.
+    pub fn help(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for mode in &[
+            Mode::Normal,
+            Mode::Search,
+            Mode::Command,
+            Mode::Goto,
+            Mode::Replace,
+            Mode::Exclude,
+        ] {
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.push(format!("{} mode", mode));
+
+            for binding in self.bindings.iter().filter(|binding| binding.mode == *mode) {
+                lines.push(format!(
+                    "  {:<12}{}",
+                    key_name(binding.key),
+                    binding.description
+                ));
+            }
+        }
+
+        lines.push(String::new());
+        lines.push(
+            "Press j and k to scroll, Ctrl-c to quit, or any other key to close this help."
+                .to_string(),
+        );
+
+        lines
+    }
+}
+
+// Return a human readable name of a key, e.g. `Ctrl-f`.
+pub fn key_name(key: Key) -> String {
+    match key {
+        Key::Char('\t') => "Tab".to_string(),
+        Key::BackTab => "Shift-Tab".to_string(),
+        Key::Char('\n') => "Enter".to_string(),
+        Key::Char(' ') => "Space".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("Ctrl-{}", c),
+        Key::Alt(c) => format!("Alt-{}", c),
+        Key::F(n) => format!("F{}", n),
+        Key::PageUp => "PgUp".to_string(),
+        Key::PageDown => "PgDn".to_string(),
+        key => format!("{:?}", key),
+    }
+}
+
+// Parse the name of a key, as returned by `key_name()`. Return `None` when the name isn't
+// recognized.
+pub fn parse_key(name: &str) -> Option<Key> {
+    let key = match name {
+        "Tab" => Key::Char('\t'),
+        "Shift-Tab" => Key::BackTab,
+        "Enter" => Key::Char('\n'),
+        "Space" => Key::Char(' '),
+        "PgUp" => Key::PageUp,
+        "PgDn" => Key::PageDown,
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "Backspace" => Key::Backspace,
+        "Delete" => Key::Delete,
+        "Insert" => Key::Insert,
+        "Esc" => Key::Esc,
+        name => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Key::Char(c),
+                _ if name.starts_with("Ctrl-") => Key::Ctrl(single_char(&name[5..])?),
+                _ if name.starts_with("Alt-") => Key::Alt(single_char(&name[4..])?),
+                _ if name.starts_with('F') => Key::F(name[1..].parse().ok()?),
+                _ => return None,
+            }
+        }
+    };
+
+    Some(key)
+}
+
+// Return the only character of the string.
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+
+    #[test]
+    fn remapped_key_starts_a_search() {
+        let mut state = State::new();
+        state.keymap = KeyMap::default().unbind(Mode::Normal, Key::Char('/')).bind(
+            Mode::Normal,
+            Key::Char('f'),
+            Action::Search,
+            "Search",
+        );
+
+        state.process_key(Key::Char('/')).unwrap();
+        assert_eq!(state.mode, Mode::Normal);
+        state.process_key(Key::Char('f')).unwrap();
+        assert_eq!(state.mode, Mode::Search);
+        assert!(state
+            .keymap
+            .help()
+            .contains(&format!("  {:<12}Search", "f")));
+    }
+
+    #[test]
+    fn key_names_are_parsed_back() {
+        for &key in &[
+            Key::Char('a'),
+            Key::Char('\n'),
+            Key::BackTab,
+            Key::Ctrl('f'),
+            Key::Alt('x'),
+            Key::F(5),
+            Key::PageDown,
+        ] {
+            assert_eq!(parse_key(&key_name(key)), Some(key));
+        }
+        assert_eq!(parse_key("Ctrl-ab"), None);
+    }
+}
@@ -0,0 +1,26 @@
+mod app;
+mod clipboard;
+mod config;
+mod error;
+mod history;
+mod input;
+mod keymap;
+mod render;
+mod search;
+mod state;
+mod terminal;
+mod theme;
+
+pub use crate::app::{App, Lines, Result};
+pub use crate::config::Config;
+pub use crate::error::AppError;
+pub use crate::history::default_path as default_history_path;
+#[cfg(feature = "async")]
+pub use crate::input::StreamSource;
+pub use crate::input::{LineSource, Reader};
+pub use crate::keymap::{Action, KeyMap};
+pub use crate::render::{Cell, Grid, Recorder, Renderer, Style, Terminal};
+pub use crate::search::{find_matches, rank, score, LineMatch, Matcher, SearchOptions};
+pub use crate::state::{Anchor, Mode, Placement, Timestamps};
+pub use crate::terminal::set_panic_hook;
+pub use crate::theme::Theme;
@@ -1,64 +1,101 @@
 #![allow(dead_code)]
-mod app;
-mod error;
 mod ocpp;
-mod state;
 
 extern crate nix;
 
-pub use crate::app::App;
 use crossbeam::channel;
+use log_parser::{default_history_path, App, AppError, Config, SearchOptions};
 use serde_json::Value;
 use std::env;
-use std::fs::File;
 use std::io;
-use std::io::{BufRead, BufReader, ErrorKind};
 use std::thread;
-use termion::input::TermRead;
+use termion::get_tty;
+use termion::input::{MouseTerminal, TermRead};
 use termion::raw::IntoRawMode;
-use termion::{get_tty, is_tty};
 
-fn main() -> app::Result<()> {
-    //let args: Vec<String> = env::args().collect();
-    //let input = match args.get(1) {
-    //Some(file) => {
-    //let f = File::open(file).expect(&format!("unable to open file '{}'", file).to_owned());
-    //BufReader::new(f)
-    //}
-    //None => return Err(io::Error::new(ErrorKind::NotFound, "file not found"))?,
-    //};
+fn main() -> log_parser::Result<()> {
+    // Lines are read from the file given as argument, or from STDIN if there is none. With
+    // `--batch <query>`, the lines matching the query are printed without starting the UI. With
+    // `--json <query>` they're printed as JSON.
+    let args: Vec<String> = env::args().collect();
+
+    // Options are read from the configuration file, if there is one.
+    let config = match Config::default_path() {
+        Some(path) => {
+            let config = Config::load(&path)?;
+            for warning in &config.warnings {
+                eprintln!("{}: {}", path.display(), warning);
+            }
+            config
+        }
+        None => Config::default(),
+    };
+    if let (Some(mode @ ("--batch" | "--json")), Some(query)) =
+        (args.get(1).map(String::as_str), args.get(2))
+    {
+        let highlight = termion::is_tty(&io::stdout());
+        let app = match args.get(3) {
+            Some(file) => App::new(io::stdout()).open(file)?,
+            None => App::new(io::stdout()),
+        };
+        let mut app = app.config(&config);
+        if mode == "--json" {
+            return app.run_json(query, SearchOptions::default());
+        }
+        return app.run_batch(query, SearchOptions::default(), highlight);
+    }
 
-    let (s1, keys) = channel::unbounded();
+    let (s1, events) = channel::unbounded();
 
-    let stdout = io::stdout()
-        .into_raw_mode()
-        .expect("failed to put STDOUT into raw mode");
-    let tty = get_tty().expect("failed to obtain TTY");
+    log_parser::set_panic_hook();
 
-    let mut app = App::new(stdout);
+    // Mouse reporting is enabled for as long as `stdout` lives, so the scroll wheel can be used.
+    let stdout = MouseTerminal::from(
+        io::stdout()
+            .into_raw_mode()
+            .map_err(AppError::TerminalError)?,
+    );
+    let tty = get_tty().map_err(AppError::TerminalError)?;
+
+    let app = match args.get(1) {
+        Some(file) => App::new(stdout).open(file)?,
+        None => App::new(stdout),
+    };
+    let mut app = app.config(&config);
+    if let Some(path) = default_history_path() {
+        app = app.history_file(path);
+    }
 
     thread::spawn(move || {
-        for key in tty.keys() {
-            match key {
-                Ok(key) => {
+        for event in tty.events() {
+            match event {
+                Ok(event) => {
                     // TODO: properly return a Result containing the error.
-                    s1.send(key).unwrap()
+                    s1.send(event).unwrap()
                 }
                 Err(_) => {
                     // TODO: properly return a Result containing the error.
                     // I've already tried something like:
-                    //    return error::AppError::IOError(e);
+                    //    return error::AppError::TerminalError(e);
                     //
                     // But that fails to compile with:
                     //
-                    // for key in tty.keys() {
-                    //          ^^^^^^^^^^ expected enum `error::AppError`, found ()
+                    // for event in tty.events() {
+                    //            ^^^^^^^^^^^^ expected enum `error::AppError`, found ()
                 }
             }
         }
     });
 
-    app.start(keys)
+    // Warnings are printed once the app is gone and the screen has been cleaned up.
+    let result = app.start(events);
+    let warnings = app.warnings().to_vec();
+    drop(app);
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+
+    result
 }
 
 fn parse_json(data: &str) -> std::result::Result<ocpp::Message, ocpp::ParseError> {
@@ -109,8 +146,8 @@ fn parse_json(data: &str) -> std::result::Result<ocpp::Message, ocpp::ParseError
             };
 
             Ok(ocpp::Message::Call {
-                unique_id: unique_id,
-                action: action,
+                unique_id,
+                action,
                 data: data.clone(),
             })
         }
@@ -126,7 +163,7 @@ fn parse_json(data: &str) -> std::result::Result<ocpp::Message, ocpp::ParseError
             };
 
             Ok(ocpp::Message::CallResult {
-                unique_id: unique_id,
+                unique_id,
                 data: data.clone(),
             })
         }
@@ -152,9 +189,9 @@ fn parse_json(data: &str) -> std::result::Result<ocpp::Message, ocpp::ParseError
             };
 
             Ok(ocpp::Message::CallError {
-                unique_id: unique_id,
-                error_code: error_code,
-                error_description: error_description,
+                unique_id,
+                error_code,
+                error_description,
             })
         }
         _ => Err(ocpp::ParseError),
@@ -0,0 +1,286 @@
+// Drawing the frames of the app. A frame is a line for every row of the screen, with escape
+// sequences for the colors. Usually frames are written to a terminal, but they can also be
+// recorded as grids of cells, e.g. to check what the app draws.
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use termion::clear;
+use termion::cursor;
+
+pub trait Renderer {
+    // Draw the frame. When `cursor` is set, the cursor is shown in that column of the last row.
+    fn render(&mut self, frame: &[String], cursor: Option<usize>) -> io::Result<()>;
+}
+
+// Writes frames to a terminal. Only rows that differ from the previous frame are written, that
+// avoids flickering.
+pub struct Terminal<W: Write> {
+    output: W,
+
+    // The rows that were written to the output during the last redraw.
+    prev_frame: Vec<String>,
+}
+
+impl<W: Write> Terminal<W> {
+    pub fn new(output: W) -> Self {
+        Terminal {
+            output,
+            prev_frame: Vec::new(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    // Forget what's on the screen, so the next frame is written in full. E.g. after the terminal
+    // has been resized, it may have moved the text around.
+    pub fn invalidate(&mut self) {
+        self.prev_frame.clear();
+    }
+}
+
+impl<W: Write> Renderer for Terminal<W> {
+    // When writing fails, it's unknown what ended up on the screen. The next frame is written in
+    // full then.
+    fn render(&mut self, frame: &[String], cursor: Option<usize>) -> io::Result<()> {
+        let written = self.write_frame(frame, cursor);
+        if written.is_err() {
+            self.prev_frame.clear();
+        }
+        written
+    }
+}
+
+impl<W: Write> Terminal<W> {
+    fn write_frame(&mut self, frame: &[String], cursor: Option<usize>) -> io::Result<()> {
+        for (i, row) in frame.iter().enumerate() {
+            if self.prev_frame.get(i) == Some(row) && self.prev_frame.len() == frame.len() {
+                continue;
+            }
+
+            write!(
+                self.output,
+                "{}{}{}",
+                cursor::Goto(1, i as u16 + 1),
+                clear::CurrentLine,
+                row
+            )?;
+        }
+
+        // The cursor is only shown while typing.
+        match cursor {
+            Some(column) => write!(
+                self.output,
+                "{}{}",
+                cursor::Goto(column as u16 + 1, frame.len() as u16),
+                cursor::Show
+            )?,
+            None => write!(self.output, "{}", cursor::Hide)?,
+        }
+        self.output.flush()?;
+        self.prev_frame = frame.to_vec();
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Terminal<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// The style of a cell. Colors are numbers of the 256 color palette, colors that aren't set are
+// the default colors of the terminal.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Style {
+    pub fg: Option<u8>,
+    pub bg: Option<u8>,
+    pub bold: bool,
+    pub underlined: bool,
+    pub inverted: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Cell {
+    pub c: char,
+    pub style: Style,
+}
+
+// A recorded frame, a row of cells for every row of the screen. Rows only contain the cells that
+// were drawn, so they can be shorter than the screen is wide.
+pub type Grid = Vec<Vec<Cell>>;
+
+// Records every frame as a grid of cells. Clones share the recorded frames, so a clone can be
+// given to the app while the frames are read from the original.
+#[derive(Debug, Default, Clone)]
+pub struct Recorder {
+    frames: Arc<Mutex<Vec<Grid>>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    // Return all frames that have been recorded so far, the oldest first.
+    pub fn frames(&self) -> Vec<Grid> {
+        self.frames.lock().unwrap().clone()
+    }
+
+    pub fn last_frame(&self) -> Option<Grid> {
+        self.frames.lock().unwrap().last().cloned()
+    }
+}
+
+impl Renderer for Recorder {
+    fn render(&mut self, frame: &[String], _cursor: Option<usize>) -> io::Result<()> {
+        let grid = frame.iter().map(|row| cells(row)).collect();
+        self.frames.lock().unwrap().push(grid);
+
+        Ok(())
+    }
+}
+
+// Split a row into cells, applying the escape sequences that set the style. Other escape
+// sequences, like the ones clearing a line, are skipped.
+fn cells(row: &str) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    let mut style = Style::default();
+    let mut chars = row.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            cells.push(Cell { c, style });
+            continue;
+        }
+
+        if chars.peek() != Some(&'[') {
+            continue;
+        }
+        chars.next();
+
+        let mut parameters = String::new();
+        for c in &mut chars {
+            if c.is_ascii_alphabetic() {
+                if c == 'm' {
+                    apply(&mut style, &parameters);
+                }
+                break;
+            }
+            parameters.push(c);
+        }
+    }
+
+    cells
+}
+
+// Apply the parameters of an SGR escape sequence, like `1;38;5;3` of `\x1b[1;38;5;3m`, to the style.
+fn apply(style: &mut Style, parameters: &str) {
+    let mut parameters = parameters.split(';').map(|p| p.parse::<u8>().unwrap_or(0));
+
+    while let Some(parameter) = parameters.next() {
+        match parameter {
+            0 => *style = Style::default(),
+            1 => style.bold = true,
+            22 => style.bold = false,
+            4 => style.underlined = true,
+            24 => style.underlined = false,
+            7 => style.inverted = true,
+            27 => style.inverted = false,
+            30..=37 => style.fg = Some(parameter - 30),
+            90..=97 => style.fg = Some(parameter - 90 + 8),
+            40..=47 => style.bg = Some(parameter - 40),
+            100..=107 => style.bg = Some(parameter - 100 + 8),
+            38 | 48 => {
+                // Only colors of the 256 color palette are used, those are written as `38;5;n`.
+                let color = match (parameters.next(), parameters.next()) {
+                    (Some(5), Some(color)) => Some(color),
+                    _ => None,
+                };
+                if parameter == 38 {
+                    style.fg = color;
+                } else {
+                    style.bg = color;
+                }
+            }
+            39 => style.fg = None,
+            49 => style.bg = None,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use termion::color::{self, AnsiValue};
+    use termion::style;
+
+    #[test]
+    fn unchanged_rows_are_not_written_again() {
+        let mut terminal = Terminal::new(Vec::new());
+        let frame = vec!["first".to_string(), "second".to_string()];
+        terminal.render(&frame, None).unwrap();
+        terminal.get_mut().clear();
+
+        terminal.render(&frame, None).unwrap();
+        let written = String::from_utf8(terminal.get_mut().clone()).unwrap();
+        assert!(!written.contains(&clear::All.to_string()));
+        assert!(!written.contains("first"));
+        assert!(!written.contains("second"));
+    }
+
+    #[test]
+    fn frames_are_recorded_as_cells() {
+        let mut recorder = Recorder::new();
+        let frame = vec![
+            format!(
+                "an {}{}err{}{}or",
+                color::Fg(AnsiValue(1)),
+                style::Bold,
+                style::Reset,
+                color::Bg(AnsiValue(226))
+            ),
+            format!("{}plain{}", clear::CurrentLine, style::Invert),
+        ];
+        recorder.clone().render(&frame, None).unwrap();
+
+        let grid = recorder.last_frame().unwrap();
+        assert_eq!(recorder.frames().len(), 1);
+        assert_eq!(grid[0].len(), 8);
+        assert_eq!(
+            grid[0][0],
+            Cell {
+                c: 'a',
+                style: Style::default()
+            }
+        );
+        let bold_red = Style {
+            fg: Some(1),
+            bold: true,
+            ..Style::default()
+        };
+        assert_eq!(
+            grid[0][3],
+            Cell {
+                c: 'e',
+                style: bold_red
+            }
+        );
+        assert_eq!(grid[0][6].style.bg, Some(226));
+        assert_eq!(grid[0][6].style.fg, None);
+        assert_eq!(
+            grid[1].iter().map(|cell| cell.c).collect::<String>(),
+            "plain"
+        );
+        assert!(grid[1].iter().all(|cell| cell.style == Style::default()));
+
+        recorder.render(&frame, None).unwrap();
+        assert_eq!(recorder.frames().len(), 2);
+    }
+}
@@ -0,0 +1,481 @@
+// The matching engine. It doesn't depend on the terminal, so it can be used on its own.
+use regex::Regex;
+use std::cmp;
+use std::ops::Range;
+use unicode_width::UnicodeWidthChar;
+
+// The prefix of the names of the groups that capture the characters of a fuzzy query. Only those
+// characters are highlighted, not the text in between them.
+const FUZZY_GROUP: &str = "fuzzy";
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SearchOptions {
+    pub case_insensitive: bool,
+
+    // The search is case insensitive unless the query contains an uppercase character.
+    pub smart_case: bool,
+
+    // Match the query verbatim instead of interpreting it as a regex.
+    pub literal: bool,
+
+    // Only match whole words.
+    pub whole_word: bool,
+
+    // Match lines that contain the characters of the query in the same order, with anything in
+    // between them, like fzf does. `err` matches `error` as well as `every run`.
+    pub fuzzy: bool,
+
+    // Split the query on whitespace and only match lines that contain every term, in any order.
+    pub all_terms: bool,
+
+    // Only match at the start, or at the end, of a line.
+    pub anchor_start: bool,
+    pub anchor_end: bool,
+
+    // Only search the columns of a line from `start_column` up to, but not including,
+    // `end_column`. Anchors match at the start and the end of those columns then.
+    pub start_column: Option<usize>,
+    pub end_column: Option<usize>,
+}
+
+// Matches lines against one or more regexes. A line matches when every regex matches it.
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    regexes: Vec<Regex>,
+
+    // For every regex, whether it's a fuzzy query.
+    fuzzy: Vec<bool>,
+
+    // The columns of a line that are searched, see `SearchOptions`.
+    start_column: Option<usize>,
+    end_column: Option<usize>,
+}
+
+impl Matcher {
+    pub fn new(patterns: &[String]) -> Result<Self, regex::Error> {
+        let regexes = patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        let fuzzy = regexes
+            .iter()
+            .map(|re| {
+                re.capture_names()
+                    .flatten()
+                    .any(|name| name.starts_with(FUZZY_GROUP))
+            })
+            .collect();
+        Ok(Matcher {
+            regexes,
+            fuzzy,
+            start_column: None,
+            end_column: None,
+        })
+    }
+
+    // Only search the columns of a line from `start` up to, but not including, `end`.
+    pub fn set_columns(&mut self, start: Option<usize>, end: Option<usize>) {
+        self.start_column = start;
+        self.end_column = end;
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        let (_, searched) = self.searched(line);
+        self.regexes.iter().all(|re| re.is_match(searched))
+    }
+
+    // Return the part of the line that is searched, and the byte offset of that part in the
+    // line. Wide characters take up more than one column, a character that only partly falls
+    // in the columns is left out.
+    fn searched<'a>(&self, line: &'a str) -> (usize, &'a str) {
+        if self.start_column.is_none() && self.end_column.is_none() {
+            return (0, line);
+        }
+
+        let mut start = None;
+        let mut end = line.len();
+        let mut column = 0;
+        for (i, c) in line.char_indices() {
+            if self
+                .end_column
+                .is_some_and(|end_column| column + c.width().unwrap_or(0) > end_column)
+            {
+                end = i;
+                break;
+            }
+            if start.is_none() && column >= self.start_column.unwrap_or(0) {
+                start = Some(i);
+            }
+            column += c.width().unwrap_or(0);
+        }
+        let start = cmp::min(start.unwrap_or(line.len()), end);
+
+        (start, &line[start..end])
+    }
+
+    // Return the byte ranges of the line that matched any of the regexes. Overlapping ranges are
+    // merged, so the ranges are ordered and don't overlap. Of a fuzzy query, only the matched
+    // characters are returned.
+    pub fn spans(&self, line: &str) -> Vec<Range<usize>> {
+        let (offset, searched) = self.searched(line);
+        let mut all: Vec<Range<usize>> = Vec::new();
+        for (re, &fuzzy) in self.regexes.iter().zip(&self.fuzzy) {
+            if !fuzzy {
+                all.extend(
+                    re.find_iter(searched)
+                        .map(|m| m.start() + offset..m.end() + offset),
+                );
+                continue;
+            }
+
+            for captures in re.captures_iter(searched) {
+                all.extend(
+                    captures
+                        .iter()
+                        .skip(1)
+                        .flatten()
+                        .map(|m| m.start() + offset..m.end() + offset),
+                );
+            }
+        }
+        all.sort_by_key(|span| span.start);
+
+        let mut spans: Vec<Range<usize>> = Vec::new();
+        for span in all {
+            match spans.last_mut() {
+                Some(last) if span.start <= last.end => last.end = cmp::max(last.end, span.end),
+                _ => spans.push(span),
+            }
+        }
+        spans
+    }
+
+    // Return the capture groups of the first match of every regex in the line, as pairs of the
+    // name or index of the group and the text it captured. Groups that didn't take part in the
+    // match have no text.
+    pub fn captures(&self, line: &str) -> Vec<(String, Option<String>)> {
+        let (_, searched) = self.searched(line);
+        let mut groups = Vec::new();
+        for re in &self.regexes {
+            let captures = match re.captures(searched) {
+                Some(captures) => captures,
+                None => continue,
+            };
+
+            for (i, name) in re.capture_names().enumerate().skip(1) {
+                if name.is_some_and(|name| name.starts_with(FUZZY_GROUP)) {
+                    continue;
+                }
+                let label = match name {
+                    Some(name) => name.to_string(),
+                    None => i.to_string(),
+                };
+                groups.push((label, captures.get(i).map(|m| m.as_str().to_string())));
+            }
+        }
+        groups
+    }
+}
+
+// A line that matched the query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineMatch {
+    // The index of the line.
+    pub line: usize,
+
+    // The byte ranges of the line that matched the query.
+    pub spans: Vec<Range<usize>>,
+}
+
+// Return the query as a regex pattern with the search options applied.
+pub fn pattern(query: &str, options: &SearchOptions) -> String {
+    let case_insensitive =
+        options.case_insensitive || (options.smart_case && !query.chars().any(char::is_uppercase));
+
+    // Every character of a fuzzy query is captured in its own group, so that only the characters
+    // are highlighted.
+    let query = if options.fuzzy {
+        query
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                format!(
+                    "(?P<{}{}>{})",
+                    FUZZY_GROUP,
+                    i,
+                    regex::escape(&c.to_string())
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(".*?")
+    } else if options.literal {
+        regex::escape(query)
+    } else {
+        query.to_string()
+    };
+
+    // An empty query stays empty, `\b\b` would match in between every word. Whole words don't
+    // mean much for a fuzzy query.
+    let query = if options.whole_word && !options.fuzzy && !query.is_empty() {
+        format!(r"\b(?:{})\b", query)
+    } else {
+        query
+    };
+
+    let query = match (options.anchor_start, options.anchor_end) {
+        (false, false) => query,
+        (true, false) => format!("^(?:{})", query),
+        (false, true) => format!("(?:{})$", query),
+        (true, true) => format!("^(?:{})$", query),
+    };
+
+    if case_insensitive {
+        return format!("(?i){}", query);
+    }
+
+    query
+}
+
+// Return the patterns that a line must match, one for every term when searching for all terms,
+// otherwise only one for the whole query. An empty query has no patterns.
+pub fn patterns(query: &str, options: &SearchOptions) -> Vec<String> {
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    if options.all_terms {
+        return query
+            .split_whitespace()
+            .map(|term| pattern(term, options))
+            .collect();
+    }
+
+    vec![pattern(query, options)]
+}
+
+// Return all lines that match the query. An empty query, or a query that isn't a valid regex,
+// doesn't match any line.
+pub fn find_matches(lines: &[String], query: &str, options: SearchOptions) -> Vec<LineMatch> {
+    let patterns = patterns(query, &options);
+    if patterns.is_empty() {
+        return Vec::new();
+    }
+
+    match Matcher::new(&patterns) {
+        Ok(mut matcher) => {
+            matcher.set_columns(options.start_column, options.end_column);
+            find(&matcher, lines)
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+// Return all lines that match.
+pub fn find<'a, I>(matcher: &Matcher, lines: I) -> Vec<LineMatch>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    lines
+        .into_iter()
+        .enumerate()
+        .filter(|(_, line)| matcher.is_match(line))
+        .map(|(i, line)| LineMatch {
+            line: i,
+            spans: matcher.spans(line),
+        })
+        .collect()
+}
+
+// Return how well the matched parts fit together, the number of characters in between the first
+// and the last span that weren't matched. The lower the score, the better the match. For a fuzzy
+// query, `error` scores better for `err` than `every run` does.
+pub fn score(line: &LineMatch) -> usize {
+    let (first, last) = match (line.spans.first(), line.spans.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return 0,
+    };
+    let matched: usize = line.spans.iter().map(|span| span.len()).sum();
+
+    (last.end - first.start).saturating_sub(matched)
+}
+
+// Sort the matches by their score, the best match first. Matches with the same score stay in the
+// order of their lines.
+pub fn rank(matches: &mut [LineMatch]) {
+    matches.sort_by_key(score);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Return the indices of the lines that match the query.
+    fn matching(lines: &[&str], query: &str, options: SearchOptions) -> Vec<usize> {
+        let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        find_matches(&lines, query, options)
+            .into_iter()
+            .map(|m| m.line)
+            .collect()
+    }
+
+    #[test]
+    fn literal_query() {
+        let options = SearchOptions {
+            literal: true,
+            ..SearchOptions::default()
+        };
+        let lines = ["version 1.2.3", "version 1a2b3", "version 1.2"];
+
+        assert_eq!(matching(&lines, "1.2.3", options), vec![0]);
+        assert_eq!(
+            matching(&lines, "1.2.3", SearchOptions::default()),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn smart_case() {
+        let options = SearchOptions {
+            smart_case: true,
+            ..SearchOptions::default()
+        };
+        let lines = ["an ERROR", "an error", "an Error"];
+
+        assert_eq!(matching(&lines, "error", options), vec![0, 1, 2]);
+        assert_eq!(matching(&lines, "Error", options), vec![2]);
+    }
+
+    #[test]
+    fn whole_word() {
+        let options = SearchOptions {
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        let matcher = Matcher::new(&patterns("log", &options)).unwrap();
+
+        assert_eq!(matcher.spans("a dialog log"), vec![9..12]);
+        assert!(!matcher.is_match("a dialog"));
+    }
+
+    #[test]
+    fn find_matches_returns_every_match() {
+        let lines: Vec<String> = ["foo and foo", "bar", "a foo"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        let found: Vec<(usize, Vec<(usize, usize)>)> =
+            find_matches(&lines, "foo", SearchOptions::default())
+                .into_iter()
+                .map(|m| (m.line, m.spans.iter().map(|s| (s.start, s.end)).collect()))
+                .collect();
+
+        assert_eq!(found, vec![(0, vec![(0, 3), (8, 11)]), (2, vec![(2, 5)])]);
+        assert!(find_matches(&lines, "baz", SearchOptions::default()).is_empty());
+        assert!(find_matches(&lines, "", SearchOptions::default()).is_empty());
+        assert!(find_matches(&lines, "(", SearchOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn every_term_must_match() {
+        let options = SearchOptions {
+            all_terms: true,
+            ..SearchOptions::default()
+        };
+        let lines = [
+            "timeout in db",
+            "db is up",
+            "a timeout",
+            "db, then a timeout",
+        ];
+
+        assert_eq!(matching(&lines, "timeout db", options), vec![0, 3]);
+
+        // Every term is highlighted on its own.
+        let matcher = Matcher::new(&patterns("timeout db", &options)).unwrap();
+        let spans: Vec<(usize, usize)> = matcher
+            .spans("db, then a timeout")
+            .iter()
+            .map(|span| (span.start, span.end))
+            .collect();
+        assert_eq!(spans, vec![(0, 2), (11, 18)]);
+    }
+
+    #[test]
+    fn anchored_to_the_start_of_the_line() {
+        let options = SearchOptions {
+            anchor_start: true,
+            ..SearchOptions::default()
+        };
+        let lines = ["error: disk full", "an error", "Error: again"];
+        assert_eq!(matching(&lines, "error", options), vec![0]);
+
+        // The anchor composes with the other options.
+        let options = SearchOptions {
+            case_insensitive: true,
+            ..options
+        };
+        assert_eq!(matching(&lines, "error", options), vec![0, 2]);
+        let options = SearchOptions {
+            anchor_end: true,
+            literal: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(matching(&["costs 5$", "5$ costs"], "5$", options), vec![0]);
+    }
+
+    #[test]
+    fn only_the_columns_are_searched() {
+        let options = SearchOptions {
+            start_column: Some(6),
+            end_column: Some(12),
+            ..SearchOptions::default()
+        };
+        let lines = [
+            "12:00 ERROR disk full",
+            "ERROR at the start",
+            "12:00 INFO  ERROR",
+        ];
+        assert_eq!(matching(&lines, "ERROR", options), vec![0]);
+
+        // The spans are in bytes of the whole line, also after wide characters.
+        let mut matcher = Matcher::new(&["x".to_string()]).unwrap();
+        matcher.set_columns(Some(2), None);
+        let spans: Vec<(usize, usize)> = matcher
+            .spans("x全x")
+            .iter()
+            .map(|span| (span.start, span.end))
+            .collect();
+        assert_eq!(spans, vec![(4, 5)]);
+
+        // Anchors match at the edges of the columns.
+        let options = SearchOptions {
+            anchor_start: true,
+            ..options
+        };
+        assert_eq!(matching(&lines, "ERROR", options), vec![0]);
+    }
+
+    #[test]
+    fn fuzzy_query_matches_scattered_characters() {
+        let options = SearchOptions {
+            fuzzy: true,
+            ..SearchOptions::default()
+        };
+        let lines = ["aXbXc", "cba", "abc"];
+        assert_eq!(matching(&lines, "abc", options), vec![0, 2]);
+
+        let matcher = Matcher::new(&patterns("abc", &options)).unwrap();
+        let spans: Vec<(usize, usize)> = matcher
+            .spans("aXbXc")
+            .iter()
+            .map(|span| (span.start, span.end))
+            .collect();
+        assert_eq!(spans, vec![(0, 1), (2, 3), (4, 5)]);
+
+        // Closer matches rank higher.
+        let lines: Vec<String> = ["a--b--c", "abc"].iter().map(|l| l.to_string()).collect();
+        let mut found = find_matches(&lines, "abc", options);
+        rank(&mut found);
+        assert_eq!(found[0].line, 1);
+    }
+}
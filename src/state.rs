@@ -1,12 +1,70 @@
 use crate::app;
-use crate::error;
+use crate::keymap::{Action, KeyMap};
+use crate::search::{self, SearchOptions};
+use serde::Deserialize;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use termion::event::Key;
+use std::mem;
+use std::ops::Range;
+use termion::event::{Event, Key, MouseButton, MouseEvent};
+use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Debug, Copy, Clone)]
+// The number of lines a single step of the scroll wheel scrolls.
+const WHEEL_LINES: usize = 3;
+
+// The number of columns the view is panned at a time.
+const PAN_COLUMNS: usize = 8;
+
+// Where a line that is jumped to ends up on the screen.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Placement {
+    Top,
+    Center,
+    Bottom,
+}
+
+// Which edge of the screen the lines stick to. Anchored to the bottom, the most recent line is on
+// the bottom row, like `tail -f` does. Anchored to the top, the view starts at the first line and
+// lines are drawn from the top row down, like a pager does.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Anchor {
+    Top,
+    Bottom,
+}
+
+// What the gutter shows of the time a line was read: nothing, the time of the day, or the time
+// since the app started.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Timestamps {
+    Off,
+    Clock,
+    Elapsed,
+}
+
+// What the user asked to copy to the clipboard.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CopyTarget {
+    // The cursor line, or else the current match, or else the line at the bottom of the screen.
+    Line,
+    Query,
+}
+
+// In the configuration file, modes are written in lowercase.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Mode {
     Normal,
     Search,
+    Help,
+    Stats,
+    Command,
+    Goto,
+    Replace,
+    Exclude,
 }
 
 impl fmt::Display for Mode {
@@ -17,48 +75,1274 @@ impl fmt::Display for Mode {
 
 pub struct State {
     pub mode: Mode,
+    pub keymap: KeyMap,
     pub query: Vec<char>,
+
+    // The position of the cursor in the query, as an index into `query`.
+    pub cursor: usize,
+
+    // The query when search mode was left. Starting a new search starts from this query.
+    pub last_query: Vec<char>,
+
+    pub options: SearchOptions,
+
+    // When set, lines that don't match the query are hidden.
+    pub filter: bool,
+
+    // When set, lines that match the query are hidden.
+    pub invert: bool,
+
+    // In filter mode, the number of lines before and after a match that are shown as well.
+    pub context: usize,
+
+    // When set, lines that are wider than the screen are wrapped instead of cut off.
+    pub wrap: bool,
+
+    // When not set, matches aren't highlighted. Jumping between matches still works.
+    pub highlight: bool,
+
+    // When set, log levels like `ERROR` are colored, regardless of the query.
+    pub highlight_levels: bool,
+
+    // When set, whitespace and control characters are shown as glyphs, like `·` for a space.
+    pub show_whitespace: bool,
+
+    // When set, every line is prefixed with its line number.
+    pub show_line_numbers: bool,
+
+    // When lines were read, if they are shown in the gutter.
+    pub timestamps: Timestamps,
+
+    // When set, the groups captured by the query are shown while searching.
+    pub show_captures: bool,
+
+    // When set, consecutive identical lines are shown as a single line with a repeat counter.
+    pub dedup: bool,
+
+    // When set, a block of lines that is repeated right after itself is shown only once.
+    // `blocks` contains the lines of every repeated block, it's updated on every redraw. Blocks
+    // that have been expanded again are in `expanded_blocks`, by their first line.
+    pub collapse_blocks: bool,
+    pub blocks: Vec<Range<usize>>,
+    pub expanded_blocks: HashSet<usize>,
+
+    // The number of rows the view has been scrolled up. Without filtering, folding or hiding,
+    // every line has a row of its own. Otherwise `rows` contains the index of the line that every
+    // row shows, oldest first. It's updated on every redraw.
+    pub scroll_offset: usize,
+    pub rows: Option<Vec<usize>>,
+
+    // The number of lines that have been added since the last redraw. They move the view up,
+    // when it isn't following the input, as far as they take up rows.
+    pub new_lines: usize,
+
+    // The number of lines that Up and Down scroll, when there's no cursor line.
+    pub scroll_step: usize,
+
+    // Where lines that are jumped to, like matches, end up on the screen.
+    pub placement: Placement,
+
+    // The number of columns the view has been panned to the right, and how far it can be panned.
+    // The latter is updated on every redraw.
+    pub h_offset: usize,
+    pub max_h_offset: usize,
+
+    // The index of the line the cursor is on. Without a cursor line, the arrow keys scroll the
+    // view instead of moving the cursor.
+    pub cursor_line: Option<usize>,
+
+    // When set, the view sticks to the most recent line as new input comes in.
+    pub following: bool,
+    pub anchor: Anchor,
+
+    // The number of lines that fit on the screen and how far the view can be scrolled up. Both
+    // are updated on every redraw.
+    pub height: usize,
+    pub max_scroll_offset: usize,
+    pub buffer_len: usize,
+
+    // How many lines of the help screen are scrolled out of view at the top. The redraw clamps
+    // it to the length of the help.
+    pub help_offset: usize,
+
+    // The patterns of the last search. `match_lines` contains the indices of the lines in the
+    // buffer that match it, `current_match` is the line that was jumped to last.
+    pub search: Option<Vec<String>>,
+    pub match_lines: Vec<usize>,
+
+    // For every line in `match_lines`, the columns at which its matches start.
+    pub match_columns: Vec<Vec<usize>>,
+
+    // The column of the match on the current match that was panned to last.
+    pub match_column: Option<usize>,
+
+    // When set, jumping past the last match continues at the first match, and the other way
+    // around.
+    pub wrap_search: bool,
+
+    // The indices of the lines that look like errors, in ascending order.
+    pub error_lines: Vec<usize>,
+    pub current_match: Option<usize>,
+
+    // Queries that have been searched for, the most recent one last. While browsing through the
+    // history, `history_index` points to the query that has been recalled.
+    pub history: Vec<String>,
+    pub history_index: Option<usize>,
+
+    // Lines that have been marked, by the name of the mark.
+    pub marks: HashMap<char, usize>,
+
+    // The first key of a sequence of keys, like `gg`, while waiting for the next key.
+    pub pending: Option<Key>,
+
+    // The command that is being typed in command mode.
+    pub command: Vec<char>,
+
+    // The patterns of queries that have been pinned. Their matches stay highlighted, each query
+    // in its own color, while other queries are searched for.
+    pub pinned: Vec<Vec<String>>,
+
+    // The regex and the replacement that are typed in replace mode. While in replace mode, the
+    // lines are shown with the replacement applied, the buffer itself is left alone.
+    // `editing_replacement` is set while the replacement is typed, instead of the regex.
+    pub replace_pattern: Vec<char>,
+    pub replacement: Vec<char>,
+    pub editing_replacement: bool,
+
+    // The digits of the line number that is being typed in goto mode.
+    pub goto: String,
+
+    // Lines matching this regex are hidden, when it's set. It's edited in exclude mode.
+    pub exclude: Vec<char>,
+
+    // A message for the user, e.g. about an invalid command. It's shown until the next key press.
+    pub message: Option<String>,
+
+    // Set when the user asked to quit.
+    pub quit: bool,
+
+    // Set when the user asked to save the lines that are shown to a file.
+    pub save: bool,
+
+    // Set when the user asked to copy something to the clipboard.
+    pub copy: Option<CopyTarget>,
 }
 
 impl State {
     pub fn new() -> Self {
         State {
             mode: Mode::Normal,
+            keymap: KeyMap::default(),
             query: Vec::new(),
+            cursor: 0,
+            last_query: Vec::new(),
+            options: SearchOptions::default(),
+            filter: false,
+            invert: false,
+            wrap: false,
+            highlight: true,
+            highlight_levels: false,
+            show_whitespace: false,
+            show_line_numbers: false,
+            timestamps: Timestamps::Off,
+            show_captures: false,
+            dedup: false,
+            collapse_blocks: false,
+            blocks: Vec::new(),
+            expanded_blocks: HashSet::new(),
+            scroll_offset: 0,
+            rows: None,
+            new_lines: 0,
+            scroll_step: 1,
+            context: 0,
+            placement: Placement::Center,
+            h_offset: 0,
+            max_h_offset: 0,
+            cursor_line: None,
+            following: true,
+            anchor: Anchor::Bottom,
+            height: 0,
+            max_scroll_offset: 0,
+            buffer_len: 0,
+            help_offset: 0,
+            search: None,
+            match_lines: Vec::new(),
+            match_columns: Vec::new(),
+            match_column: None,
+            wrap_search: true,
+            error_lines: Vec::new(),
+            current_match: None,
+            history: Vec::new(),
+            history_index: None,
+            marks: HashMap::new(),
+            pending: None,
+            command: Vec::new(),
+            goto: String::new(),
+            exclude: Vec::new(),
+            replace_pattern: Vec::new(),
+            replacement: Vec::new(),
+            editing_replacement: false,
+            pinned: Vec::new(),
+            message: None,
+            quit: false,
+            save: false,
+            copy: None,
+        }
+    }
+
+    // Scroll the view, the offset is clamped to what the buffer allows. New input is only
+    // followed as long as the view is at the bottom.
+    fn scroll_to(&mut self, offset: usize) {
+        self.scroll_offset = cmp::min(offset, self.max_scroll_offset);
+        self.following = self.scroll_offset == 0;
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        self.scroll_to(self.scroll_offset + lines);
+    }
+
+    fn scroll_down(&mut self, lines: usize) {
+        self.scroll_to(self.scroll_offset.saturating_sub(lines));
+    }
+
+    // Return the number of rows the lines take up.
+    fn row_count(&self) -> usize {
+        match self.rows {
+            Some(ref rows) => rows.len(),
+            None => self.buffer_len,
+        }
+    }
+
+    // Return the row, counted from the oldest row, that shows the line. A line that isn't shown
+    // maps to the closest row before it, like the line a run of repeated lines is folded into.
+    fn row(&self, line: usize) -> usize {
+        match self.rows {
+            Some(ref rows) => rows.partition_point(|&i| i <= line).saturating_sub(1),
+            None => line,
+        }
+    }
+
+    // Return the index of the line that the row shows.
+    fn line(&self, row: usize) -> usize {
+        match self.rows {
+            Some(ref rows) => rows.get(row).or(rows.last()).copied().unwrap_or(0),
+            None => row,
+        }
+    }
+
+    // Return the index of the line in the middle of the screen.
+    fn center_line(&self) -> usize {
+        self.line(
+            self.row_count()
+                .saturating_sub(1 + self.scroll_offset + self.height / 2),
+        )
+    }
+
+    // Scroll so that the given line ends up where `placement` says, as far as possible.
+    fn place(&mut self, line: usize) {
+        let (rows, row) = (self.row_count(), self.row(line));
+        let offset = match self.placement {
+            Placement::Top => rows.saturating_sub(row + self.height),
+            Placement::Center => rows.saturating_sub(1 + row + self.height / 2),
+            Placement::Bottom => rows.saturating_sub(1 + row),
+        };
+        self.scroll_to(offset);
+    }
+
+    // Jump to the line at the percentage of the lines that are shown, e.g. to the middle for 50.
+    // Percentages over 100 jump to the last line.
+    fn seek(&mut self, percentage: usize) {
+        let rows = self.row_count();
+        let row = cmp::min(percentage, 100) * rows / 100;
+        self.place(self.line(cmp::min(row, rows.saturating_sub(1))));
+    }
+
+    // Scroll to a match, and make it the current match.
+    fn show_match(&mut self, line: usize) {
+        self.place(line);
+        self.current_match = Some(line);
+        self.match_column = None;
+    }
+
+    // Jump to the first match after the current match. If there's no current match, jump to the
+    // first match after the middle of the screen. Wraps around at the end of the buffer, unless
+    // `wrap_search` is unset.
+    fn next_match(&mut self) {
+        let current = self.current_match.unwrap_or_else(|| self.center_line());
+        self.jump(next_line(&self.match_lines, current), Direction::Down);
+    }
+
+    // Jump to the next match of the search, while staying in search mode. A query that hasn't
+    // been searched for yet is committed instead: search mode is left and its matches stay
+    // highlighted.
+    fn search_next(&mut self) {
+        let patterns = self.patterns();
+        if patterns.is_empty() || self.search.as_ref() != Some(&patterns) {
+            return self.leave_search(true);
+        }
+        self.next_match();
+    }
+
+    // Like `next_match()`, but in the other direction.
+    fn previous_match(&mut self) {
+        let current = self.current_match.unwrap_or_else(|| self.center_line());
+        self.jump(previous_line(&self.match_lines, current), Direction::Up);
+    }
+
+    // Pan to the next match on the current match, or on the line at the top of the screen when
+    // there's no current match. After the last match on the line, jump to the first match on the
+    // next line with matches.
+    fn next_match_in_line(&mut self) {
+        let line = self.current_match.unwrap_or_else(|| self.top_line());
+        let column =
+            self.match_columns(line)
+                .iter()
+                .copied()
+                .find(|&column| match self.match_column {
+                    Some(current) => column > current,
+                    None => column >= self.h_offset,
+                });
+        if let Some(column) = column {
+            return self.pan_to_match(line, column);
+        }
+
+        if !self.jump(next_line(&self.match_lines, line), Direction::Down) {
+            return;
+        }
+        if let Some(line) = self.current_match {
+            if let Some(&column) = self.match_columns(line).first() {
+                self.pan_to_match(line, column);
+            }
+        }
+    }
+
+    // Like `next_match_in_line()`, but in the other direction.
+    fn previous_match_in_line(&mut self) {
+        let line = self.current_match.unwrap_or_else(|| self.top_line());
+        let column = self
+            .match_columns(line)
+            .iter()
+            .copied()
+            .rev()
+            .find(|&column| match self.match_column {
+                Some(current) => column < current,
+                None => column < self.h_offset,
+            });
+        if let Some(column) = column {
+            return self.pan_to_match(line, column);
+        }
+
+        if !self.jump(previous_line(&self.match_lines, line), Direction::Up) {
+            return;
+        }
+        if let Some(line) = self.current_match {
+            if let Some(&column) = self.match_columns(line).last() {
+                self.pan_to_match(line, column);
+            }
+        }
+    }
+
+    // Return the columns at which the matches on the line start.
+    fn match_columns(&self, line: usize) -> &[usize] {
+        match self.match_lines.binary_search(&line) {
+            Ok(index) => self.match_columns.get(index).map_or(&[], Vec::as_slice),
+            Err(_) => &[],
+        }
+    }
+
+    // Make the line the current match and pan so that the match at the column is at the left of
+    // the screen.
+    fn pan_to_match(&mut self, line: usize, column: usize) {
+        self.current_match = Some(line);
+        self.match_column = Some(column);
+        self.h_offset = column;
+    }
+
+    // Like `next_match()`, but jumps to the next error instead, regardless of the query.
+    fn next_error(&mut self) {
+        let current = self.current_match.unwrap_or_else(|| self.center_line());
+        self.jump(next_line(&self.error_lines, current), Direction::Down);
+    }
+
+    // Like `next_error()`, but in the other direction.
+    fn previous_error(&mut self) {
+        let current = self.current_match.unwrap_or_else(|| self.center_line());
+        self.jump(previous_line(&self.error_lines, current), Direction::Up);
+    }
+
+    // Jump to the line that was found by `next_line()` or `previous_line()`. The user is told
+    // when the search reached the end of the buffer, either because it wrapped around or because
+    // it stopped there. Returns whether a line was jumped to.
+    fn jump(&mut self, found: Found, direction: Direction) -> bool {
+        let (end, start) = match direction {
+            Direction::Down => ("BOTTOM", "TOP"),
+            Direction::Up => ("TOP", "BOTTOM"),
+        };
+
+        match found {
+            Found::Line(line) => self.show_match(line),
+            Found::Wrapped(_) if !self.wrap_search => {
+                self.message = Some(format!("search hit {}", end));
+                return false;
+            }
+            Found::Wrapped(line) => {
+                self.show_match(line);
+                self.message = Some(format!("search hit {}, continuing at {}", end, start));
+            }
+            Found::Nothing => return false,
+        }
+
+        true
+    }
+
+    // Add the query to the history, unless it's empty or the same as the previous query.
+    fn add_to_history(&mut self) {
+        let query: String = self.query.iter().collect();
+        if query.is_empty() || self.history.last() == Some(&query) {
+            return;
+        }
+
+        self.history.push(query);
+    }
+
+    // Replace the query by an older or a newer query from the history. Going past the most recent
+    // query clears the query.
+    fn recall_history(&mut self, older: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+
+        self.history_index = match (self.history_index, older) {
+            (None, true) => Some(self.history.len() - 1),
+            (None, false) => None,
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < self.history.len() => Some(i + 1),
+            (Some(_), false) => None,
+        };
+
+        let query = match self.history_index {
+            Some(i) => self.history[i].chars().collect(),
+            None => Vec::new(),
+        };
+        self.set_query(query);
+    }
+
+    // Scroll so that the given line ends up at the top of the screen, as far as possible.
+    fn show_at_top(&mut self, line: usize) {
+        let offset = self
+            .row_count()
+            .saturating_sub(self.row(line) + self.height);
+        self.scroll_to(offset);
+    }
+
+    // Execute the command typed in command mode. These are supported:
+    //
+    //     :q       quit
+    //     :<n>     jump to line <n>
+    //     :<n>%    jump to <n> percent of the lines
+    //     :noh     stop highlighting matches, until the query changes
+    //     :unpin   stop highlighting the pinned queries
+    //     :columns <start>..<end>
+    //              only search columns <start> up to <end>, either can be left out
+    //     :columns search all columns
+    fn run_command(&mut self) {
+        let command: String = self.command.iter().collect();
+        match command.trim() {
+            "q" => self.quit = true,
+            "noh" => self.highlight = false,
+            "unpin" => self.pinned = Vec::new(),
+            "columns" => {
+                self.options.start_column = None;
+                self.options.end_column = None;
+            }
+            command if command.starts_with("columns ") => {
+                match parse_columns(command["columns ".len()..].trim()) {
+                    Some((start, end)) => {
+                        self.options.start_column = start;
+                        self.options.end_column = end;
+                    }
+                    None => self.message = Some(format!("Not a column range: {}", command)),
+                }
+            }
+            command if command.ends_with('%') => {
+                match command.trim_end_matches('%').parse::<usize>() {
+                    Ok(percentage) => self.seek(percentage),
+                    Err(_) => self.message = Some(format!("Not a command: {}", command)),
+                }
+            }
+            command => match command.parse::<usize>() {
+                Ok(line) => self.place(line.saturating_sub(1)),
+                Err(_) => self.message = Some(format!("Not a command: {}", command)),
+            },
+        }
+    }
+
+    // Handle the second key of a sequence. Any key that doesn't complete a sequence cancels it.
+    fn finish_sequence(&mut self, first: Key, key: Key) {
+        match (first, key) {
+            (Key::Char('g'), Key::Char('g')) => self.scroll_to(self.max_scroll_offset),
+            (Key::Char('m'), Key::Char(name)) if name.is_ascii_alphabetic() => {
+                let top = self.top_line();
+                self.marks.insert(name, top);
+            }
+            (Key::Char('\''), Key::Char(name)) => match self.marks.get(&name) {
+                Some(&line) => self.show_at_top(line),
+                None => self.message = Some(format!("Mark not set: {}", name)),
+            },
+            _ => (),
+        }
+    }
+
+    // Move the cursor up or down by a number of rows, and scroll to keep it on the screen.
+    fn move_cursor_line(&mut self, line: usize, rows: isize) {
+        let last = self.row_count().saturating_sub(1);
+        let row = cmp::min(self.row(line).saturating_add_signed(rows), last);
+        self.cursor_line = Some(self.line(row));
+
+        let top = self.top_row();
+        let bottom = last.saturating_sub(self.scroll_offset);
+        if row < top {
+            self.scroll_up(top - row);
+        } else if row > bottom {
+            self.scroll_down(row - bottom);
+        }
+    }
+
+    // Return the row at the top of the screen, counted from the oldest row.
+    fn top_row(&self) -> usize {
+        self.row_count()
+            .saturating_sub(self.scroll_offset + self.height)
+    }
+
+    // Return the index of the line at the top of the screen.
+    fn top_line(&self) -> usize {
+        self.line(self.top_row())
+    }
+
+    // Anchor the lines to the top or the bottom of the screen. Only the view anchored to the
+    // bottom follows the input from the start, the other one stays at the first line.
+    pub fn set_anchor(&mut self, anchor: Anchor) {
+        self.anchor = anchor;
+        self.following = anchor == Anchor::Bottom;
+    }
+
+    // Update the indices of the current match, the cursor line and the marks after the oldest line
+    // has been dropped from the buffer. Marks on the dropped line are removed, a cursor on the
+    // dropped line moves to the next line.
+    pub fn drop_first_line(&mut self) {
+        self.current_match = self.current_match.and_then(|i| i.checked_sub(1));
+        self.cursor_line = self.cursor_line.map(|i| i.saturating_sub(1));
+        self.marks = self
+            .marks
+            .drain()
+            .filter_map(|(name, line)| line.checked_sub(1).map(|line| (name, line)))
+            .collect();
+        self.error_lines = self
+            .error_lines
+            .drain(..)
+            .filter_map(|line| line.checked_sub(1))
+            .collect();
+        self.expanded_blocks = self
+            .expanded_blocks
+            .drain()
+            .filter_map(|line| line.checked_sub(1))
+            .collect();
+    }
+
+    // Expand the repeated block at the cursor line, or collapse it again. Without a cursor line,
+    // the block in the middle of the screen is used.
+    fn toggle_block(&mut self) {
+        let line = self.cursor_line.unwrap_or_else(|| self.center_line());
+        if let Some(block) = self.blocks.iter().find(|block| block.contains(&line)) {
+            if !self.expanded_blocks.remove(&block.start) {
+                self.expanded_blocks.insert(block.start);
+            }
+        }
+    }
+
+    // Go back to normal mode. The query is remembered for the next search, unless it has been
+    // cleared. Unless `keep_query` is set, the query is cleared, so nothing is highlighted
+    // anymore.
+    fn leave_search(&mut self, keep_query: bool) {
+        self.add_to_history();
+        self.history_index = None;
+        let patterns = self.patterns();
+        if !patterns.is_empty() && self.search.as_ref() != Some(&patterns) {
+            self.search = Some(patterns);
+            self.current_match = None;
+        }
+        self.mode = Mode::Normal;
+        self.last_query = if keep_query {
+            self.query.clone()
+        } else {
+            mem::take(&mut self.query)
+        };
+        self.cursor = 0;
+    }
+
+    // Replace the query and put the cursor at its end.
+    fn set_query(&mut self, query: Vec<char>) {
+        self.cursor = query.len();
+        self.query = query;
+        self.highlight = true;
+    }
+
+    // Called after the query has been edited.
+    fn query_edited(&mut self) {
+        self.history_index = None;
+        self.highlight = true;
+    }
+
+    // Delete the word in front of the cursor, including the whitespace between the word and the
+    // cursor.
+    fn delete_word(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.query[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.query[start - 1].is_whitespace() {
+            start -= 1;
+        }
+
+        self.query.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    // Return the query as regex patterns with the search options applied.
+    pub fn patterns(&self) -> Vec<String> {
+        let query: String = self.query.iter().collect();
+        search::patterns(&query, &self.options)
+    }
+
+    // Handle a key press or a mouse event. The scroll wheel scrolls a few lines at a time, other
+    // mouse events are ignored.
+    pub fn process_event(&mut self, event: Event) -> app::Result<()> {
+        match event {
+            Event::Key(key) => self.process_key(key),
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _)) => {
+                self.scroll_up(WHEEL_LINES);
+                Ok(())
+            }
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, _, _)) => {
+                self.scroll_down(WHEEL_LINES);
+                Ok(())
+            }
+            _ => Ok(()),
         }
     }
 
     pub fn process_key(&mut self, key: Key) -> app::Result<()> {
-        match (self.mode, key) {
-            // No mather which mode, ctrl-c will stop the program.
-            (_, Key::Ctrl('c')) => return Ok(()),
+        self.message = None;
+
+        // The help screen scrolls with j and k, or a page at a time with Space and b. Any other
+        // key closes it, just like it closes the statistics.
+        if let Mode::Help = self.mode {
+            let page = self.height.saturating_sub(3);
+            match key {
+                Key::Char('j') | Key::Down => self.help_offset += 1,
+                Key::Char('k') | Key::Up => self.help_offset = self.help_offset.saturating_sub(1),
+                Key::Char(' ') | Key::PageDown => self.help_offset += page,
+                Key::Char('b') | Key::PageUp => {
+                    self.help_offset = self.help_offset.saturating_sub(page)
+                }
+                _ => self.mode = Mode::Normal,
+            }
+            return Ok(());
+        }
+        if let Mode::Stats = self.mode {
+            self.mode = Mode::Normal;
+            return Ok(());
+        }
+
+        if let Some(first) = self.pending.take() {
+            self.finish_sequence(first, key);
+            return Ok(());
+        }
+
+        let action = match self.keymap.lookup(self.mode, key) {
+            Some(action) => action,
+            None => {
+                // We don't support multi-line search.
+                match (self.mode, key) {
+                    (_, Key::Char('\n')) => (),
+                    (Mode::Search, Key::Char(c)) => {
+                        self.query.insert(self.cursor, c);
+                        self.cursor += 1;
+                        self.query_edited();
+                    }
+                    (Mode::Command, Key::Char(c)) => self.command.push(c),
+                    (Mode::Replace, Key::Char(c)) if self.editing_replacement => {
+                        self.replacement.push(c)
+                    }
+                    (Mode::Replace, Key::Char(c)) => self.replace_pattern.push(c),
+                    (Mode::Exclude, Key::Char(c)) => self.exclude.push(c),
+                    (Mode::Goto, Key::Char(c))
+                        if c.is_ascii_digit() && !self.goto.ends_with('%') =>
+                    {
+                        self.goto.push(c)
+                    }
+                    (Mode::Goto, Key::Char('%'))
+                        if !self.goto.is_empty() && !self.goto.ends_with('%') =>
+                    {
+                        self.goto.push('%')
+                    }
+
+                    // Anything but a digit, or a `%` after the digits, cancels goto mode.
+                    (Mode::Goto, _) => {
+                        self.mode = Mode::Normal;
+                        self.goto = String::new();
+                    }
+                    (_, _) => (),
+                }
+                return Ok(());
+            }
+        };
+
+        match action {
+            // Scrolling. Paging keeps a single line of overlap with the previous screen.
+            Action::ScrollUp => match self.cursor_line {
+                Some(line) => self.move_cursor_line(line, -1),
+                None => self.scroll_up(self.scroll_step),
+            },
+            Action::ScrollDown => match self.cursor_line {
+                Some(line) => self.move_cursor_line(line, 1),
+                None => self.scroll_down(self.scroll_step),
+            },
+            Action::ToggleCursorLine => {
+                self.cursor_line = match self.cursor_line {
+                    Some(_) => None,
+                    None => Some(self.center_line()),
+                }
+            }
+            Action::PageUp => self.scroll_up(self.height.saturating_sub(1)),
+            Action::PageDown => self.scroll_down(self.height.saturating_sub(1)),
+            Action::Top => self.scroll_to(self.max_scroll_offset),
+            Action::Bottom => self.scroll_to(0),
+            Action::PanLeft => self.h_offset = self.h_offset.saturating_sub(PAN_COLUMNS),
+            Action::PanRight => {
+                self.h_offset = cmp::min(self.h_offset + PAN_COLUMNS, self.max_h_offset)
+            }
+            Action::StartSequence => self.pending = Some(key),
+
+            Action::NextMatch => self.next_match(),
+            Action::PreviousMatch => self.previous_match(),
+            Action::NextMatchInLine => self.next_match_in_line(),
+            Action::PreviousMatchInLine => self.previous_match_in_line(),
+            Action::NextError => self.next_error(),
+            Action::PreviousError => self.previous_error(),
+            Action::ToggleWrap => self.wrap = !self.wrap,
+            Action::ToggleDedup => self.dedup = !self.dedup,
+            Action::ToggleBlocks => self.collapse_blocks = !self.collapse_blocks,
+            Action::ToggleBlock => self.toggle_block(),
+            Action::ToggleLevels => self.highlight_levels = !self.highlight_levels,
+            Action::ToggleWhitespace => self.show_whitespace = !self.show_whitespace,
+            Action::ToggleLineNumbers => self.show_line_numbers = !self.show_line_numbers,
+            Action::CycleTimestamps => {
+                self.timestamps = match self.timestamps {
+                    Timestamps::Off => Timestamps::Clock,
+                    Timestamps::Clock => Timestamps::Elapsed,
+                    Timestamps::Elapsed => Timestamps::Off,
+                }
+            }
+            Action::Help => {
+                self.mode = Mode::Help;
+                self.help_offset = 0;
+            }
+            Action::Stats => self.mode = Mode::Stats,
+            Action::Save => self.save = true,
+            Action::CopyLine => self.copy = Some(CopyTarget::Line),
+            Action::CopyQuery => self.copy = Some(CopyTarget::Query),
 
             // Going into search mode.
-            (Mode::Normal, Key::Char('/')) => {
+            Action::Search => {
                 self.mode = Mode::Search;
-                Ok(())
+                self.set_query(self.last_query.clone());
+            }
+            Action::ToggleCaseInsensitive => {
+                self.options.case_insensitive = !self.options.case_insensitive
+            }
+            Action::ToggleLiteral => self.options.literal = !self.options.literal,
+            Action::ToggleWholeWord => self.options.whole_word = !self.options.whole_word,
+            Action::ToggleFuzzy => self.options.fuzzy = !self.options.fuzzy,
+            Action::ToggleAllTerms => self.options.all_terms = !self.options.all_terms,
+            Action::ToggleAnchorStart => self.options.anchor_start = !self.options.anchor_start,
+            Action::ToggleAnchorEnd => self.options.anchor_end = !self.options.anchor_end,
+            Action::ToggleCaptures => self.show_captures = !self.show_captures,
+            Action::ToggleFilter => self.filter = !self.filter,
+            Action::ToggleInvert => self.invert = !self.invert,
+            Action::Pin => {
+                let patterns = self.patterns();
+                if !patterns.is_empty() {
+                    self.add_to_history();
+                    self.pinned.push(patterns);
+                    self.set_query(Vec::new());
+                    self.query_edited();
+                }
+            }
+            Action::DeleteChar => {
+                if let Mode::Command = self.mode {
+                    pop_grapheme(&mut self.command);
+                } else if let Mode::Goto = self.mode {
+                    self.goto.pop();
+                } else if let Mode::Exclude = self.mode {
+                    pop_grapheme(&mut self.exclude);
+                } else if let Mode::Replace = self.mode {
+                    if self.editing_replacement {
+                        pop_grapheme(&mut self.replacement);
+                    } else {
+                        pop_grapheme(&mut self.replace_pattern);
+                    }
+                } else {
+                    let start = previous_grapheme(&self.query, self.cursor);
+                    self.query.drain(start..self.cursor);
+                    self.cursor = start;
+                    self.query_edited();
+                }
             }
 
-            // We don't support multi-line search.
-            (Mode::Search, Key::Char('\n')) => Ok(()),
-            (Mode::Search, Key::Backspace) => {
-                self.query.pop();
-                Ok(())
+            Action::DeleteWord => {
+                self.delete_word();
+                self.query_edited();
+            }
+            Action::ClearQuery => {
+                self.set_query(Vec::new());
+                self.query_edited();
             }
+            Action::CursorLeft => self.cursor = previous_grapheme(&self.query, self.cursor),
+            Action::CursorRight => self.cursor = next_grapheme(&self.query, self.cursor),
 
-            (Mode::Search, Key::Char(n)) => {
-                self.query.push(n);
-                Ok(())
+            Action::HistoryPrevious => self.recall_history(true),
+            Action::HistoryNext => self.recall_history(false),
+
+            // Leaving search mode. When the search is committed, the query stays, so its matches
+            // remain highlighted in normal mode.
+            Action::CommitSearch => self.leave_search(true),
+            Action::LeaveSearch => self.leave_search(false),
+            Action::SearchNext => self.search_next(),
+
+            Action::Command => self.mode = Mode::Command,
+            Action::RunCommand => {
+                self.run_command();
+                self.mode = Mode::Normal;
+                self.command = Vec::new();
+            }
+            Action::LeaveCommand => {
+                self.mode = Mode::Normal;
+                self.command = Vec::new();
             }
 
-            // Leaving search mode.
-            (Mode::Search, Key::Esc) => {
+            // Previewing a replacement. Running it saves the lines with the replacement applied.
+            Action::Replace => {
+                self.mode = Mode::Replace;
+                self.editing_replacement = false;
+            }
+            Action::SwitchField => self.editing_replacement = !self.editing_replacement,
+            Action::RunReplace => self.save = true,
+            Action::LeaveReplace => {
                 self.mode = Mode::Normal;
-                self.query = Vec::new();
-                Ok(())
+                self.replace_pattern = Vec::new();
+                self.replacement = Vec::new();
             }
 
-            (_, _) => Ok(()),
+            // Jumping to a line. Numbers past the last line jump to the last line.
+            Action::Goto => self.mode = Mode::Goto,
+            Action::RunGoto => {
+                if let Some(Ok(percentage)) = self.goto.strip_suffix('%').map(str::parse) {
+                    self.seek(percentage);
+                } else if let Ok(line) = self.goto.parse::<usize>() {
+                    let last = self.buffer_len.saturating_sub(1);
+                    self.place(cmp::min(line.saturating_sub(1), last));
+                }
+                self.mode = Mode::Normal;
+                self.goto = String::new();
+            }
+            Action::LeaveGoto => {
+                self.mode = Mode::Normal;
+                self.goto = String::new();
+            }
+
+            // Hiding lines. The lines are hidden while the regex is typed, leaving exclude mode
+            // without committing shows them again.
+            Action::Exclude => self.mode = Mode::Exclude,
+            Action::CommitExclude => self.mode = Mode::Normal,
+            Action::LeaveExclude => {
+                self.mode = Mode::Normal;
+                self.exclude = Vec::new();
+            }
         }
+
+        Ok(())
+    }
+}
+
+// The line found when searching for the next or the previous match.
+enum Found {
+    Line(usize),
+
+    // The search had to wrap around at the end of the buffer to find the line.
+    Wrapped(usize),
+    Nothing,
+}
+
+enum Direction {
+    Up,
+    Down,
+}
+
+// Return the first of the lines after the given line, or the first line when there are none.
+// `lines` must be in ascending order.
+fn next_line(lines: &[usize], current: usize) -> Found {
+    match lines.iter().find(|&&line| line > current) {
+        Some(&line) => Found::Line(line),
+        None => lines
+            .first()
+            .map_or(Found::Nothing, |&line| Found::Wrapped(line)),
+    }
+}
+
+// Like `next_line()`, but in the other direction.
+fn previous_line(lines: &[usize], current: usize) -> Found {
+    match lines.iter().rev().find(|&&line| line < current) {
+        Some(&line) => Found::Line(line),
+        None => lines
+            .last()
+            .map_or(Found::Nothing, |&line| Found::Wrapped(line)),
+    }
+}
+
+// Parse a column range like `30..60`, `30..` or `..60`. Columns start at 0 and the end isn't
+// included.
+fn parse_columns(range: &str) -> Option<(Option<usize>, Option<usize>)> {
+    let (start, end) = range.split_once("..")?;
+    let parse = |column: &str| -> Option<Option<usize>> {
+        match column.trim() {
+            "" => Some(None),
+            column => column.parse().ok().map(Some),
+        }
+    };
+    Some((parse(start)?, parse(end)?))
+}
+
+// Return the position in the characters where the grapheme in front of the position starts. An
+// emoji with a skin tone or a letter with a combining accent is made up of more than one
+// character, but it's a single grapheme that is edited as a whole.
+fn previous_grapheme(chars: &[char], position: usize) -> usize {
+    let before: String = chars[..position].iter().collect();
+    let len = before
+        .graphemes(true)
+        .next_back()
+        .map_or(0, |grapheme| grapheme.chars().count());
+    position - len
+}
+
+// Like `previous_grapheme()`, but returns where the grapheme after the position ends.
+fn next_grapheme(chars: &[char], position: usize) -> usize {
+    let after: String = chars[position..].iter().collect();
+    let len = after
+        .graphemes(true)
+        .next()
+        .map_or(0, |grapheme| grapheme.chars().count());
+    position + len
+}
+
+// Remove the last grapheme of the characters.
+fn pop_grapheme(chars: &mut Vec<char>) {
+    chars.truncate(previous_grapheme(chars, chars.len()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Return the state of a screen with room for `height` lines, showing a buffer of `lines`
+    // lines, like a redraw leaves it.
+    fn state(lines: usize, height: usize) -> State {
+        let mut state = State::new();
+        state.buffer_len = lines;
+        state.height = height;
+        state.max_scroll_offset = lines.saturating_sub(height);
+        state
+    }
+
+    fn press(state: &mut State, keys: &[Key]) {
+        for &key in keys {
+            state.process_key(key).unwrap();
+        }
+    }
+
+    #[test]
+    fn scrolling_stops_at_both_ends() {
+        let mut state = state(20, 5);
+
+        press(&mut state, &[Key::Down]);
+        assert_eq!(state.scroll_offset, 0);
+
+        press(&mut state, &[Key::Up, Key::Up]);
+        assert_eq!(state.scroll_offset, 2);
+        assert!(!state.following);
+
+        press(&mut state, &[Key::Up; 30]);
+        assert_eq!(state.scroll_offset, 15);
+
+        press(&mut state, &[Key::Down; 30]);
+        assert_eq!(state.scroll_offset, 0);
+        assert!(state.following);
+    }
+
+    #[test]
+    fn page_down_and_end() {
+        let mut state = state(100, 10);
+
+        press(&mut state, &[Key::Home]);
+        assert_eq!(state.scroll_offset, 90);
+
+        press(&mut state, &[Key::PageDown]);
+        assert_eq!(state.scroll_offset, 81);
+
+        press(&mut state, &[Key::PageUp, Key::PageUp, Key::End]);
+        assert_eq!(state.scroll_offset, 0);
+        assert!(state.following);
+    }
+
+    #[test]
+    fn next_and_previous_match() {
+        let mut state = state(100, 10);
+        state.match_lines = vec![10, 50, 90];
+
+        // The first match after the middle of the screen is the first line, after wrapping around.
+        let mut offsets = Vec::new();
+        for _ in 0..4 {
+            press(&mut state, &[Key::Char('n')]);
+            offsets.push(state.scroll_offset);
+        }
+        assert_eq!(offsets, vec![84, 44, 4, 84]);
+
+        press(&mut state, &[Key::Char('N')]);
+        assert_eq!(state.current_match, Some(90));
+        assert_eq!(state.scroll_offset, 4);
+    }
+
+    fn type_text(state: &mut State, text: &str) {
+        let keys: Vec<Key> = text.chars().map(Key::Char).collect();
+        press(state, &keys);
+    }
+
+    #[test]
+    fn command_jumps_to_a_line() {
+        let mut state = state(100, 10);
+        type_text(&mut state, ":42\n");
+
+        // Line 42 has index 41, it ends up in the middle of the screen.
+        assert_eq!(state.mode, Mode::Normal);
+        assert_eq!(state.scroll_offset, 53);
+        assert_eq!(state.center_line(), 41);
+    }
+
+    #[test]
+    fn history_recalls_older_queries() {
+        let mut state = state(10, 5);
+        type_text(&mut state, "/first\n");
+        type_text(&mut state, "/");
+        press(&mut state, &[Key::Ctrl('u')]);
+        type_text(&mut state, "second\n");
+        type_text(&mut state, "/");
+        press(&mut state, &[Key::Ctrl('u')]);
+
+        press(&mut state, &[Key::Up]);
+        assert_eq!(state.query.iter().collect::<String>(), "second");
+        press(&mut state, &[Key::Up]);
+        assert_eq!(state.query.iter().collect::<String>(), "first");
+        press(&mut state, &[Key::Down, Key::Down]);
+        assert!(state.query.is_empty());
+    }
+
+    #[test]
+    fn scroll_wheel_scrolls_a_few_lines() {
+        let mut state = state(100, 10);
+        let wheel = |button| Event::Mouse(MouseEvent::Press(button, 1, 1));
+
+        state.process_event(wheel(MouseButton::WheelUp)).unwrap();
+        state.process_event(wheel(MouseButton::WheelUp)).unwrap();
+        assert_eq!(state.scroll_offset, 2 * WHEEL_LINES);
+
+        state.process_event(wheel(MouseButton::WheelDown)).unwrap();
+        assert_eq!(state.scroll_offset, WHEEL_LINES);
+
+        // Clicking doesn't scroll.
+        state.process_event(wheel(MouseButton::Left)).unwrap();
+        assert_eq!(state.scroll_offset, WHEEL_LINES);
+    }
+
+    #[test]
+    fn search_starts_with_the_last_query() {
+        let mut state = state(100, 10);
+        type_text(&mut state, "/error");
+        press(&mut state, &[Key::Esc]);
+        assert_eq!(state.mode, Mode::Normal);
+        assert!(state.query.is_empty());
+
+        // The last search can still be navigated.
+        state.match_lines = vec![10];
+        press(&mut state, &[Key::Char('n')]);
+        assert_eq!(state.current_match, Some(10));
+
+        type_text(&mut state, "/");
+        assert_eq!(state.query.iter().collect::<String>(), "error");
+        assert_eq!(state.cursor, 5);
+    }
+
+    #[test]
+    fn query_is_edited_at_the_cursor() {
+        let mut state = state(10, 5);
+        type_text(&mut state, "/foo bar  baz");
+        press(&mut state, &[Key::Ctrl('w')]);
+        assert_eq!(state.query.iter().collect::<String>(), "foo bar  ");
+        press(&mut state, &[Key::Ctrl('w')]);
+        assert_eq!(state.query.iter().collect::<String>(), "foo ");
+
+        press(&mut state, &[Key::Left, Key::Left]);
+        type_text(&mut state, "x");
+        assert_eq!(state.query.iter().collect::<String>(), "foxo ");
+        assert_eq!(state.cursor, 3);
+
+        press(&mut state, &[Key::Right, Key::Backspace]);
+        assert_eq!(state.query.iter().collect::<String>(), "fox ");
+        press(&mut state, &[Key::Ctrl('u')]);
+        assert!(state.query.is_empty());
+    }
+
+    #[test]
+    fn gg_goes_to_the_top() {
+        let mut state = state(100, 10);
+        type_text(&mut state, "gg");
+        assert_eq!(state.scroll_offset, 90);
+        assert_eq!(state.pending, None);
+
+        type_text(&mut state, "G");
+        assert_eq!(state.scroll_offset, 0);
+
+        // Another key cancels the sequence, and does nothing else.
+        type_text(&mut state, "gx");
+        assert_eq!(state.scroll_offset, 0);
+        assert_eq!(state.pending, None);
+        assert_eq!(state.mode, Mode::Normal);
+        type_text(&mut state, "g");
+        assert_eq!(state.pending, Some(Key::Char('g')));
+    }
+
+    #[test]
+    fn marks_are_jumped_back_to() {
+        let mut state = state(100, 10);
+        press(&mut state, &[Key::Up; 20]);
+        type_text(&mut state, "ma");
+        assert_eq!(state.marks.get(&'a'), Some(&70));
+
+        press(&mut state, &[Key::End]);
+        type_text(&mut state, "'a");
+        assert_eq!(state.scroll_offset, 20);
+
+        type_text(&mut state, "'b");
+        assert_eq!(state.scroll_offset, 20);
+        assert_eq!(state.message.as_deref(), Some("Mark not set: b"));
+
+        // The mark moves along when an older line is dropped.
+        state.drop_first_line();
+        assert_eq!(state.marks.get(&'a'), Some(&69));
+    }
+
+    #[test]
+    fn goto_jumps_to_a_line() {
+        let mut state = state(500, 10);
+        type_text(&mut state, "L100\n");
+        assert_eq!(state.mode, Mode::Normal);
+        assert_eq!(state.center_line(), 99);
+
+        // A line past the end is the last line.
+        type_text(&mut state, "L9999\n");
+        assert_eq!(state.scroll_offset, 0);
+
+        // Anything but a digit cancels.
+        type_text(&mut state, "L12x");
+        assert_eq!(state.mode, Mode::Normal);
+        assert!(state.goto.is_empty());
+        assert_eq!(state.scroll_offset, 0);
+    }
+
+    #[test]
+    fn search_wraps_around_with_a_message() {
+        let mut wrapping = state(100, 10);
+        wrapping.match_lines = vec![10, 50, 90];
+
+        // At the bottom, the next match is the first one.
+        press(&mut wrapping, &[Key::Char('n')]);
+        assert_eq!(wrapping.current_match, Some(10));
+        assert_eq!(
+            wrapping.message.as_deref(),
+            Some("search hit BOTTOM, continuing at TOP")
+        );
+        press(&mut wrapping, &[Key::Char('n')]);
+        assert_eq!(wrapping.current_match, Some(50));
+        assert_eq!(wrapping.message, None);
+
+        // Without wrapping, the search stops at the end.
+        let mut stopping = state(100, 10);
+        stopping.match_lines = vec![10, 50, 90];
+        stopping.wrap_search = false;
+        press(&mut stopping, &[Key::Char('n')]);
+        assert_eq!(stopping.current_match, None);
+        assert_eq!(stopping.scroll_offset, 0);
+        assert_eq!(stopping.message.as_deref(), Some("search hit BOTTOM"));
+    }
+
+    #[test]
+    fn matches_are_placed_on_the_screen() {
+        // Returns the row of the screen, counted from the top, that shows the match.
+        let row_of_match = |placement| {
+            let mut state = state(100, 10);
+            state.placement = placement;
+            state.match_lines = vec![50];
+            press(&mut state, &[Key::Char('n')]);
+            50 - state.top_line()
+        };
+        assert_eq!(row_of_match(Placement::Center), 10 / 2 - 1);
+        assert_eq!(row_of_match(Placement::Top), 0);
+        assert_eq!(row_of_match(Placement::Bottom), 9);
+    }
+
+    #[test]
+    fn arrow_keys_scroll_a_few_lines() {
+        let mut state = state(100, 10);
+        state.scroll_step = 3;
+        press(&mut state, &[Key::Up]);
+        assert_eq!(state.scroll_offset, 3);
+        press(&mut state, &[Key::Up, Key::Down]);
+        assert_eq!(state.scroll_offset, 3);
+    }
+
+    #[test]
+    fn backspace_removes_a_whole_grapheme() {
+        let mut state = state(10, 5);
+        type_text(&mut state, "/a👨‍👩‍👧");
+        assert_eq!(state.query.len(), 6);
+
+        // The family is five scalars, but one Backspace removes all of them.
+        press(&mut state, &[Key::Backspace]);
+        assert_eq!(state.query, vec!['a']);
+        assert_eq!(state.cursor, 1);
+    }
+
+    #[test]
+    fn seek_jumps_to_a_percentage_of_the_lines() {
+        let mut state = state(1000, 10);
+        type_text(&mut state, ":50%\n");
+        assert_eq!(state.center_line(), 500);
+        assert!((490..=510).contains(&state.scroll_offset));
+
+        type_text(&mut state, "L10%\n");
+        assert_eq!(state.center_line(), 100);
+
+        // Past 100 percent is the last line.
+        type_text(&mut state, ":150%\n");
+        assert_eq!(state.scroll_offset, 0);
     }
 }
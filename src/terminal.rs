@@ -0,0 +1,189 @@
+// Leaving the terminal the way it was found, both when the app exits normally and when it panics.
+// Also noticing when the terminal is resized.
+use crossbeam::channel::{self, Receiver};
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::termios::{self, SetArg};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::panic;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use termion::clear;
+use termion::cursor;
+use termion::screen;
+use termion::style;
+use termion::terminal_size;
+
+// The end of the pipe that the handler of SIGWINCH writes to, once the handler is installed.
+static RESIZE_PIPE: AtomicI32 = AtomicI32::new(-1);
+static RESIZES: OnceLock<Receiver<(u16, u16)>> = OnceLock::new();
+
+// Return the escape sequences that clear the screen, reset the styling and show the cursor. The
+// app draws on the alternate screen, so leaving it brings back what was on the screen before the
+// app started.
+pub fn reset_sequence() -> String {
+    format!(
+        "{}{}{}{}{}",
+        style::Reset,
+        clear::All,
+        cursor::Goto(1, 1),
+        cursor::Show,
+        screen::ToMainScreen
+    )
+}
+
+// Return the escape sequences that switch to the alternate screen and clear it.
+pub fn start_sequence() -> String {
+    format!("{}{}", screen::ToAlternateScreen, clear::All)
+}
+
+// Wraps the output of the app. Once the app has started drawing, the screen is cleaned up when the
+// guard is dropped, no matter how the app stopped.
+pub struct Guard<W: Write> {
+    output: W,
+    active: bool,
+}
+
+impl<W: Write> Guard<W> {
+    pub fn new(output: W) -> Self {
+        Guard {
+            output,
+            active: false,
+        }
+    }
+
+    // Clean up the screen on drop. Until this is called, dropping the guard doesn't write
+    // anything.
+    pub fn activate(&mut self) {
+        self.active = true;
+    }
+}
+
+impl<W: Write> Write for Guard<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<W: Write> Drop for Guard<W> {
+    fn drop(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        // Errors can't be reported from `drop()`, and there is nothing left to do about them.
+        let _ = write!(self.output, "{}", reset_sequence());
+        let _ = self.output.flush();
+    }
+}
+
+// Install a panic hook that restores the terminal before the panic is printed. Otherwise the
+// message ends up garbled on a screen that is still in raw mode.
+//
+// This must be called before STDOUT is put into raw mode, because the terminal settings at the
+// time of the call are the ones that are restored.
+pub fn set_panic_hook() {
+    let fd = io::stdout().as_raw_fd();
+    let original = termios::tcgetattr(fd).ok().map(Mutex::new);
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "{}", reset_sequence());
+        let _ = stdout.flush();
+
+        if let Some(ref original) = original {
+            if let Ok(original) = original.lock() {
+                let _ = termios::tcsetattr(fd, SetArg::TCSANOW, &original);
+            }
+        }
+
+        default_hook(info);
+    }));
+}
+
+// Return a channel that receives the size of the terminal, as columns and rows, whenever the
+// terminal is resized. When the resizes can't be noticed, the channel is disconnected.
+//
+// A signal handler can hardly do anything safely, so the handler of SIGWINCH only writes a byte to
+// a pipe. A thread reads the pipe and sends the size.
+pub fn resizes() -> Receiver<(u16, u16)> {
+    RESIZES
+        .get_or_init(|| {
+            let (sender, receiver) = channel::unbounded();
+            let mut fds = [0; 2];
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+                return receiver;
+            }
+            let [read, write] = fds;
+            RESIZE_PIPE.store(write, Ordering::SeqCst);
+
+            let action = SigAction::new(
+                SigHandler::Handler(on_resize),
+                SaFlags::SA_RESTART,
+                SigSet::empty(),
+            );
+            if unsafe { signal::sigaction(Signal::SIGWINCH, &action) }.is_err() {
+                return receiver;
+            }
+
+            thread::spawn(move || {
+                let mut byte = 0u8;
+                loop {
+                    let n =
+                        unsafe { libc::read(read, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+                    if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    if n <= 0 {
+                        return;
+                    }
+                    if let Ok(size) = terminal_size() {
+                        if sender.send(size).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+            receiver
+        })
+        .clone()
+}
+
+extern "C" fn on_resize(_: libc::c_int) {
+    let byte = 0u8;
+    unsafe {
+        libc::write(
+            RESIZE_PIPE.load(Ordering::SeqCst),
+            &byte as *const u8 as *const libc::c_void,
+            1,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen_is_reset_when_the_guard_is_dropped() {
+        let mut output = Vec::new();
+        drop(Guard::new(&mut output));
+        assert!(output.is_empty());
+
+        let mut guard = Guard::new(&mut output);
+        guard.activate();
+        write!(guard, "a frame").unwrap();
+        drop(guard);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("a frame{}", reset_sequence())
+        );
+        assert!(reset_sequence().contains(&cursor::Show.to_string()));
+    }
+}
@@ -0,0 +1,127 @@
+use termion::color::{self, AnsiValue};
+use termion::style;
+
+// The colors used for drawing. Colors that aren't set use the default colors of the terminal.
+#[derive(Debug, Copy, Clone)]
+pub struct Theme {
+    pub match_fg: Option<AnsiValue>,
+    pub match_bg: Option<AnsiValue>,
+
+    // Matches can be drawn in bold, underlined or inverted as well, so they stand out without
+    // colors too.
+    pub match_bold: bool,
+    pub match_underline: bool,
+    pub match_reverse: bool,
+
+    // The footer spans the entire width of the screen. With `footer_invert` set, the footer is
+    // drawn in the inverted colors of the terminal instead, which some terminals don't show well.
+    pub footer_fg: Option<AnsiValue>,
+    pub footer_bg: Option<AnsiValue>,
+    pub footer_invert: bool,
+
+    // The background of the cursor line.
+    pub cursor_line_bg: Option<AnsiValue>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            // The same color as `color::Red`.
+            match_fg: Some(AnsiValue(1)),
+            match_bg: None,
+            match_bold: false,
+            match_underline: false,
+            match_reverse: false,
+            footer_fg: Some(AnsiValue(15)),
+            footer_bg: Some(AnsiValue::grayscale(6)),
+            footer_invert: false,
+            cursor_line_bg: Some(AnsiValue::grayscale(4)),
+        }
+    }
+}
+
+// The colors of pinned queries, in the order the queries are pinned. After the last color, the
+// colors are used again.
+const PIN_COLORS: [AnsiValue; 5] = [
+    AnsiValue(6), // cyan
+    AnsiValue(5), // magenta
+    AnsiValue(2), // green
+    AnsiValue(3), // yellow
+    AnsiValue(4), // blue
+];
+
+impl Theme {
+    // Return the escape sequence that starts a match.
+    pub fn match_start(&self) -> String {
+        colors(self.match_fg, self.match_bg) + &self.match_attributes()
+    }
+
+    // Return the escape sequence that starts the match that was jumped to last. It has the colors
+    // of the other matches swapped, so it stands out.
+    pub fn current_match_start(&self) -> String {
+        colors(
+            self.match_bg.or(Some(AnsiValue(15))),
+            self.match_fg.or(Some(AnsiValue(1))),
+        ) + &self.match_attributes()
+    }
+
+    fn match_attributes(&self) -> String {
+        let mut attributes = String::new();
+        if self.match_bold {
+            attributes.push_str(style::Bold.as_ref());
+        }
+        if self.match_underline {
+            attributes.push_str(style::Underline.as_ref());
+        }
+        if self.match_reverse {
+            attributes.push_str(style::Invert.as_ref());
+        }
+
+        attributes
+    }
+
+    // Return the escape sequence that starts the footer.
+    pub fn footer_start(&self) -> String {
+        if self.footer_invert {
+            return style::Invert.to_string();
+        }
+
+        colors(self.footer_fg, self.footer_bg)
+    }
+
+    // Return the escape sequence that starts the cursor line.
+    pub fn cursor_line_start(&self) -> String {
+        colors(None, self.cursor_line_bg)
+    }
+}
+
+// Return the escape sequence that starts a match of the pinned query with the given index.
+pub fn pin_start(index: usize) -> String {
+    colors(Some(PIN_COLORS[index % PIN_COLORS.len()]), None)
+}
+
+// Return the escape sequence that resets the colors and the attributes set by the other escapes
+// of the theme. Unlike `style::Reset`, it leaves everything else alone. `NoFaint` sets the
+// intensity back to normal, which also undoes bold.
+pub fn reset() -> String {
+    format!(
+        "{}{}{}{}{}",
+        color::Fg(color::Reset),
+        color::Bg(color::Reset),
+        style::NoFaint,
+        style::NoUnderline,
+        style::NoInvert
+    )
+}
+
+fn colors(fg: Option<AnsiValue>, bg: Option<AnsiValue>) -> String {
+    let mut colors = String::new();
+    if let Some(fg) = fg {
+        colors.push_str(&color::Fg(fg).to_string());
+    }
+    if let Some(bg) = bg {
+        colors.push_str(&color::Bg(bg).to_string());
+    }
+
+    colors
+}